@@ -10,10 +10,15 @@ const { Scheduler } = wasm_bindgen;
 // postMessage, or std::thread becomes available so that all the scheduling
 // can be done on the rust side, with completely invisible web workers,
 // this solution seems to be the best compromise to me. 
-function time_step(start, init_time) {
+function time_step(start, init_time, resume) {
     const current_time = performance.now();
     if (start === true) {
 	self.scheduler.start(init_time, current_time);
+    } else if (resume === true) {
+	// resuming while playing would start a second loop
+	if (!self.scheduler.resume(init_time, current_time)) {
+	    return;
+	}
     }
 
     // generate the event list ...
@@ -25,7 +30,12 @@ function time_step(start, init_time) {
 	    source_type: event.source_type,
 	    timestamp: event.timestamp,
 	    sample_id: event.sample_id,
-	    params: event.params } );	
+	    params: event.params,
+	    track: event.track,
+	    line: event.line,
+	    step: event.step,
+	    span: event.span,
+	    cycle: event.cycle } );	
     });
 
     // time compensation is necessary because the setTimeout function
@@ -48,13 +58,49 @@ wasm_bindgen("./pkg/ruffbox_pattern_bg.wasm").then(wasm => {
 	    break;
 	case 'stop':
 	    clearTimeout(self.sched_timeout);	    
+	    self.scheduler.stop();
+	    break;
+	case 'pause':
+	    clearTimeout(self.sched_timeout);
+	    self.scheduler.pause();
+	    break;
+	case 'resume':
+	    time_step(false, e.data.timestamp, true);
+	    break;
+	case 'reset':
+	    self.scheduler.reset();
+	    break;
+	case 'seek':
+	    self.scheduler.seek(e.data.bar, e.data.step);
+	    break;
+	case 'set_catch_up':
+	    self.scheduler.set_catch_up(e.data.policy);
+	    break;
+	case 'set_lookahead':
+	    self.scheduler.set_lookahead(e.data.lookahead);
+	    break;
+	case 'set_adaptive_lookahead':
+	    self.scheduler.set_adaptive_lookahead(e.data.adaptive);
 	    break;
 	case 'evaluate_loop':
-	    self.scheduler.evaluate(e.data.loop_data);
+	    // freeze commands are replaced by the frozen lines in the editor
+	    const code = self.scheduler.evaluate(e.data.loop_data);
+	    if (code !== undefined) {
+		postMessage({ type: 'code', code: code });
+	    }
 	    break;
 	case 'set_tempo':
 	    self.scheduler.set_tempo(e.data.tempo);
 	    break;
+	case 'set_bpm':
+	    self.scheduler.set_bpm(e.data.bpm);
+	    break;
+	case 'set_meter':
+	    self.scheduler.set_meter(e.data.steps_per_beat, e.data.beats_per_bar);
+	    break;
+	case 'load_tuning':
+	    self.scheduler.load_tuning(e.data.name, e.data.scl, e.data.kbm);
+	    break;
 	}
     }
 });
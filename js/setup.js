@@ -175,6 +175,11 @@ if (ctx.audioWorklet === undefined) {
 
 	    // set event scheduler message callback
 	    scheduler.onmessage = function(event) {
+		// edited code goes back to the editor ...
+		if (event.data.type === 'code') {
+		    document.getElementById('code_input').value = event.data.code;
+		    return;
+		}
 		// dispatch to sampler module ...
 		n.port.postMessage({ type: 'trigger', event: event.data })
 	    };
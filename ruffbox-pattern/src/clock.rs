@@ -0,0 +1,272 @@
+// CLOCK
+// A musical clock, counting steps (one per scheduler tick) in beats and bars.
+
+use std::ops::Range;
+
+/// Tempo and meter, plus the current position.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clock {
+    pub bpm: f64,
+    pub steps_per_beat: u32,
+    pub beats_per_bar: u32,
+    /// steps elapsed since start, i.e. the step that's generated next
+    step: u64,
+    /// tempo change in progress, if any
+    ramp: Option<TempoRamp>,
+}
+
+/// A linear tempo change, step by step.
+#[derive(Clone, Debug, PartialEq)]
+struct TempoRamp {
+    from: f64,
+    to: f64,
+    start: u64,
+    steps: u64,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::new(120.0, 4, 4)
+    }
+}
+
+impl Clock {
+    pub fn new(bpm: f64, steps_per_beat: u32, beats_per_bar: u32) -> Self {
+        Clock {
+            bpm,
+            steps_per_beat: steps_per_beat.max(1),
+            beats_per_bar: beats_per_bar.max(1),
+            step: 0,
+            ramp: None,
+        }
+    }
+
+    /// Duration of one step in milliseconds.
+    pub fn tick_ms(&self) -> f64 {
+        60000.0 / (self.bpm * self.steps_per_beat as f64)
+    }
+
+    /// Set the tempo from the duration of one step in milliseconds.
+    pub fn set_tick_ms(&mut self, tick_ms: f64) {
+        self.set_bpm(60000.0 / (tick_ms * self.steps_per_beat as f64));
+    }
+
+    /// Set the tempo right away, stopping any ramp.
+    pub fn set_bpm(&mut self, bpm: f64) {
+        self.bpm = bpm;
+        self.ramp = None;
+    }
+
+    /// Change the tempo gradually over the given number of bars, starting with the next step.
+    pub fn ramp_bpm(&mut self, from: f64, to: f64, bars: u32) {
+        self.set_bpm(from);
+        let steps = bars as u64 * self.steps_per_bar();
+        if steps == 0 {
+            self.bpm = to;
+        } else {
+            self.ramp = Some(TempoRamp {
+                from,
+                to,
+                start: self.step,
+                steps,
+            });
+        }
+    }
+
+    pub fn is_ramping(&self) -> bool {
+        self.ramp.is_some()
+    }
+
+    /// Change the meter, keeping the number of steps elapsed.
+    pub fn set_meter(&mut self, steps_per_beat: u32, beats_per_bar: u32) {
+        self.steps_per_beat = steps_per_beat.max(1);
+        self.beats_per_bar = beats_per_bar.max(1);
+    }
+
+    pub fn steps_per_bar(&self) -> u64 {
+        self.steps_per_beat as u64 * self.beats_per_bar as u64
+    }
+
+    /// Move on to the next step, following the tempo ramp if there is one.
+    pub fn advance(&mut self) {
+        self.step += 1;
+
+        if let Some(ramp) = &self.ramp {
+            let done = self.step - ramp.start;
+            if done >= ramp.steps {
+                self.bpm = ramp.to;
+                self.ramp = None;
+            } else {
+                self.bpm = ramp.from + (ramp.to - ramp.from) * done as f64 / ramp.steps as f64;
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.seek(0);
+    }
+
+    /// Jump to the given step, stopping any ramp.
+    pub fn seek(&mut self, step: u64) {
+        self.step = step;
+        self.ramp = None;
+    }
+
+    /// Steps since start.
+    pub fn total_steps(&self) -> u64 {
+        self.step
+    }
+
+    /// Whether the next step starts a new block of the given number of bars.
+    pub fn at_bar_boundary(&self, bars: u32) -> bool {
+        self.step
+            .is_multiple_of(bars.max(1) as u64 * self.steps_per_bar())
+    }
+
+    /// Current bar, counting from 0.
+    pub fn bar(&self) -> u64 {
+        self.step / self.steps_per_bar()
+    }
+
+    /// Current beat within the bar, counting from 0.
+    pub fn beat(&self) -> u32 {
+        ((self.step % self.steps_per_bar()) / self.steps_per_beat as u64) as u32
+    }
+
+    /// Current step within the beat, counting from 0.
+    pub fn step_in_beat(&self) -> u32 {
+        (self.step % self.steps_per_beat as u64) as u32
+    }
+}
+
+/// When evaluated code takes effect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quantize {
+    /// right away
+    Off,
+    /// at the next boundary of that many bars
+    Bars(u32),
+    /// when the cycle of the given line wraps around
+    Line(usize),
+}
+
+/// The steps of a line running at the given rate, (events, ticks), that fall into a tick,
+/// counted on the line's own grid.
+pub fn line_steps_in_tick(rate: (u64, u64), tick: u64) -> Range<u64> {
+    let (events, ticks) = rate;
+    (tick * events).div_ceil(ticks)..((tick + 1) * events).div_ceil(ticks)
+}
+
+/// Offsets (as fraction of a tick) of the steps of a line running at the given rate,
+/// (events, ticks), that fall into a tick. Counting in whole steps keeps uneven rates
+/// from drifting.
+pub fn steps_in_tick(rate: (u64, u64), tick: u64) -> Vec<f64> {
+    let (events, ticks) = rate;
+    line_steps_in_tick(rate, tick)
+        .map(|step| (step * ticks) as f64 / events as f64 - tick as f64)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_duration() {
+        let mut clock = Clock::new(120.0, 4, 4);
+        assert_eq!(clock.tick_ms(), 125.0);
+
+        clock.set_tick_ms(250.0);
+        assert_eq!(clock.bpm, 60.0);
+
+        let triplets = Clock::new(120.0, 3, 4);
+        assert!((triplets.tick_ms() - 166.666).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_position() {
+        let mut clock = Clock::new(120.0, 4, 3);
+        assert_eq!((clock.bar(), clock.beat(), clock.step_in_beat()), (0, 0, 0));
+
+        for _ in 0..18 {
+            clock.advance();
+        }
+        // 12 steps per bar, so this is the 3rd step of the 2nd beat in the 2nd bar
+        assert_eq!((clock.bar(), clock.beat(), clock.step_in_beat()), (1, 1, 2));
+
+        // 36 steps are three bars
+        clock.advance();
+        assert!(!clock.at_bar_boundary(1));
+        for _ in 19..36 {
+            clock.advance();
+        }
+        assert!(clock.at_bar_boundary(1));
+        assert!(clock.at_bar_boundary(3));
+        assert!(!clock.at_bar_boundary(2));
+
+        clock.reset();
+        assert_eq!(clock.total_steps(), 0);
+    }
+
+    #[test]
+    fn test_tempo_ramp() {
+        let mut clock = Clock::new(120.0, 4, 4);
+        clock.ramp_bpm(120.0, 140.0, 1);
+        assert!(clock.is_ramping());
+
+        // the elapsed time is the sum of the tick lengths along the way
+        let mut elapsed = 0.0;
+        for _ in 0..8 {
+            elapsed += clock.tick_ms();
+            clock.advance();
+        }
+        assert_eq!(clock.bpm, 130.0);
+        let expected: f64 = (0..8)
+            .map(|step| 15000.0 / (120.0 + 20.0 * step as f64 / 16.0))
+            .sum();
+        assert!((elapsed - expected).abs() < 1e-9);
+
+        for _ in 0..8 {
+            clock.advance();
+        }
+        assert_eq!(clock.bpm, 140.0);
+        assert!(!clock.is_ramping());
+
+        // setting the tempo stops the ramp
+        clock.ramp_bpm(140.0, 100.0, 2);
+        clock.advance();
+        clock.set_bpm(90.0);
+        clock.advance();
+        assert_eq!(clock.bpm, 90.0);
+
+        // and so does jumping around
+        clock.ramp_bpm(90.0, 100.0, 2);
+        clock.seek(40);
+        assert!(!clock.is_ramping());
+        assert_eq!((clock.bar(), clock.beat()), (2, 2));
+    }
+
+    #[test]
+    fn test_line_rates() {
+        let half: Vec<Vec<f64>> = (0..4).map(|tick| steps_in_tick((1, 2), tick)).collect();
+        assert_eq!(half, [vec![0.0], vec![], vec![0.0], vec![]]);
+
+        assert_eq!(steps_in_tick((2, 1), 5), [0.0, 0.5]);
+
+        // three against four, the steps fall between the ticks
+        let polymeter: Vec<Vec<f64>> = (0..5).map(|tick| steps_in_tick((3, 4), tick)).collect();
+        assert_eq!(polymeter[0], [0.0]);
+        assert!((polymeter[1][0] - 1.0 / 3.0).abs() < 1e-9);
+        assert!((polymeter[2][0] - 2.0 / 3.0).abs() < 1e-9);
+        assert!(polymeter[3].is_empty());
+        assert_eq!(polymeter[4], [0.0]);
+
+        // still on the grid after a long time
+        assert_eq!(steps_in_tick((3, 4), 4_000_000), [0.0]);
+
+        // the line counts its own steps
+        assert_eq!(line_steps_in_tick((2, 1), 5), 10..12);
+        assert_eq!(line_steps_in_tick((1, 2), 4), 2..3);
+        assert!(line_steps_in_tick((1, 2), 5).is_empty());
+    }
+}
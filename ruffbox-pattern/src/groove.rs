@@ -0,0 +1,102 @@
+// GROOVE
+// Timing offsets and level scaling per step, applied when the events are scheduled.
+
+/// A groove template, cycling over its steps.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Groove {
+    /// timing offset of each step, as fraction of a step (negative is early)
+    pub offsets: Vec<f32>,
+    /// level factor of each step
+    pub levels: Vec<f32>,
+}
+
+impl Groove {
+    /// Build a groove from (offset, level) pairs, one per step.
+    pub fn from_steps(steps: &[(f32, f32)]) -> Result<Self, String> {
+        if steps.is_empty() {
+            return Err("no steps given".to_string());
+        }
+        for (idx, (offset, level)) in steps.iter().enumerate() {
+            if offset.abs() >= 1.0 {
+                return Err(format!(
+                    "step {}: offset {} not within a step",
+                    idx + 1,
+                    offset
+                ));
+            }
+            if *level < 0.0 {
+                return Err(format!("step {}: negative level {}", idx + 1, level));
+            }
+        }
+        Ok(Groove {
+            offsets: steps.iter().map(|(offset, _)| *offset).collect(),
+            levels: steps.iter().map(|(_, level)| *level).collect(),
+        })
+    }
+
+    /// Swing in percent, MPC-style: 50 is straight, 66 is a triplet feel.
+    /// Every second step is delayed so it falls at that percentage of the pair.
+    pub fn swing(percent: f32) -> Self {
+        Groove {
+            offsets: vec![0.0, swing_offset(percent)],
+            levels: vec![1.0, 1.0],
+        }
+    }
+
+    /// The built-in templates.
+    pub fn builtin(name: &str) -> Option<Self> {
+        let steps: &[(f32, f32)] = match name {
+            "straight" => &[(0.0, 1.0)],
+            // light 16th swing with accents on the beat
+            "mpc16" => &[(0.0, 1.0), (0.16, 0.8), (0.0, 0.9), (0.16, 0.8)],
+            "shuffle" => &[(0.0, 1.0), (0.32, 0.75)],
+            // ahead of the beat, except for the downbeat
+            "push" => &[(0.0, 1.0), (-0.06, 0.85), (-0.03, 0.95), (-0.08, 0.85)],
+            "laidback" => &[(0.04, 1.0), (0.1, 0.8), (0.06, 0.9), (0.12, 0.8)],
+            _ => return None,
+        };
+        Groove::from_steps(steps).ok()
+    }
+
+    /// Timing offset (in steps) and level factor for the given step.
+    pub fn at(&self, step: u64) -> (f32, f32) {
+        let idx = (step % self.offsets.len() as u64) as usize;
+        (self.offsets[idx], self.levels[idx])
+    }
+}
+
+/// Offset of every second step for a swing percentage, clamped to a sensible range.
+pub fn swing_offset(percent: f32) -> f32 {
+    2.0 * percent.clamp(50.0, 75.0) / 100.0 - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swing() {
+        let straight = Groove::swing(50.0);
+        assert_eq!(straight.at(1), (0.0, 1.0));
+
+        let triplets = Groove::swing(66.0);
+        assert_eq!(triplets.at(0), (0.0, 1.0));
+        assert!((triplets.at(1).0 - 0.32).abs() < 0.0001);
+        assert!((triplets.at(3).0 - 0.32).abs() < 0.0001);
+
+        // way too much swing is clamped
+        assert_eq!(Groove::swing(120.0).at(1).0, 0.5);
+    }
+
+    #[test]
+    fn test_templates() {
+        let mpc = Groove::builtin("mpc16").unwrap();
+        assert_eq!(mpc.at(1), (0.16, 0.8));
+        assert_eq!(mpc.at(6), (0.0, 0.9));
+        assert!(Groove::builtin("nope").is_none());
+
+        assert!(Groove::from_steps(&[(0.0, 1.0), (0.1, 0.5)]).is_ok());
+        assert!(Groove::from_steps(&[(1.5, 1.0)]).is_err());
+        assert!(Groove::from_steps(&[]).is_err());
+    }
+}
@@ -1,32 +1,57 @@
+pub mod clock;
+pub mod groove;
+pub mod lookahead;
 #[allow(clippy::type_complexity)]
 pub mod parser;
+pub mod pitch;
+pub mod scala;
 pub mod seqgen;
 
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::hash::{Hash, Hasher};
 use wasm_bindgen::prelude::*;
 
+use crate::clock::{line_steps_in_tick, steps_in_tick, Clock, Quantize};
+use crate::groove::{swing_offset, Groove};
+use crate::lookahead::{JitterTracker, MAX_LOOKAHEAD_MS, MIN_LOOKAHEAD_MS};
+use crate::parser::{Event, GenDef, ParamExpr, PatternLine, Statement};
+use crate::pitch::{chord_name, chord_voicing, note_to_freq, Key};
+use crate::scala::{parse_kbm, parse_scl, Tuning};
 use crate::seqgen::*;
 use serde::{Deserialize, Serialize};
 
 use decorum::N32;
 
 // A macro to provide `println!(..)`-style syntax for `console.log` logging.
+// Outside the browser (i.e. in the tests), there's no console, so it goes to stderr.
 macro_rules! log {
-    ( $( $t:tt )* ) => {
-        web_sys::console::log_1(&format!( $( $t )* ).into())
-    }
+    ( $( $t:tt )* ) => {{
+        #[cfg(target_arch = "wasm32")]
+        web_sys::console::log_1(&format!( $( $t )* ).into());
+        #[cfg(not(target_arch = "wasm32"))]
+        eprintln!( $( $t )* );
+    }}
 }
 
 type EventHash = u64;
 
+/// How many generated events per line are kept around for freezing.
+const FREEZE_HISTORY: usize = 256;
+
+/// Parameters every event has, unless set otherwise.
+const DEFAULT_PARAMS: &[(&str, f32)] = &[("pos", 0.5)];
+
+/// Seeking further than this replays too many events to be done in the audio worker.
+const MAX_SEEK_BARS: u32 = 1000;
+
 fn calculate_hash<T: Hash>(t: &T) -> u64 {
     let mut s = DefaultHasher::new();
     t.hash(&mut s);
     s.finish()
 }
 
+#[derive(Clone)]
 struct MainEvent {
     name: String,
     params: HashMap<String, N32>,
@@ -78,168 +103,524 @@ impl PartialEq for MainEvent {
     }
 }
 
+/// Register an event in the lookup table, return its hash.
+fn register_event(
+    event_refs: &mut HashMap<EventHash, MainEvent>,
+    parsed_event: &Event,
+) -> EventHash {
+    let main_event = MainEvent::from_parsed_input(parsed_event.0.to_string(), &parsed_event.1);
+    let main_event_hash = calculate_hash::<MainEvent>(&main_event);
+    event_refs.insert(main_event_hash, main_event);
+    main_event_hash
+}
+
+/// Create the event generator from its definition. The events are registered in the lookup table.
+fn event_generator(
+    def: &GenDef<Event>,
+    event_refs: &mut HashMap<EventHash, MainEvent>,
+    state: usize,
+) -> Box<dyn SequenceGenerator<EventHash, usize>> {
+    match def {
+        GenDef::Items(func_name, events) => {
+            let event_hashes: Vec<EventHash> = events
+                .iter()
+                .map(|ev| register_event(event_refs, ev))
+                .collect();
+
+            match *func_name {
+                "rnd" => Box::new(RandomSequenceGenerator::from_seq(&event_hashes)),
+                "shuffle" => Box::new(ShuffleSequenceGenerator::from_seq(&event_hashes)),
+                "norepeat" => Box::new(NoRepeatSequenceGenerator::from_seq(&event_hashes)),
+                "cyc" => Box::new(CycleSequenceGenerator::from_seq_with_index(
+                    &event_hashes,
+                    state,
+                )),
+                _ => Box::new(CycleSequenceGenerator::from_seq(&event_hashes)),
+            }
+        }
+        GenDef::Markov(transitions) => {
+            let hashed: Vec<(EventHash, EventHash, f32)> = transitions
+                .iter()
+                .map(|(src, dst, prob)| {
+                    (
+                        register_event(event_refs, src),
+                        register_event(event_refs, dst),
+                        *prob,
+                    )
+                })
+                .collect();
+            let sources: Vec<EventHash> = hashed.iter().map(|t| t.0).collect();
+            checked_or_fallback(
+                "markov chain",
+                MarkovSequenceGenerator::from_transitions(&hashed),
+                &sources,
+            )
+        }
+        GenDef::Infer(rules) => {
+            let hashed: Vec<(Vec<EventHash>, EventHash, f32)> = rules
+                .iter()
+                .map(|(source, symbol, prob)| {
+                    (
+                        source
+                            .iter()
+                            .map(|ev| register_event(event_refs, ev))
+                            .collect(),
+                        register_event(event_refs, symbol),
+                        *prob,
+                    )
+                })
+                .collect();
+            inferred_generator(&hashed)
+        }
+        GenDef::Learn(options, events) => {
+            let event_hashes: Vec<EventHash> = events
+                .iter()
+                .map(|ev| register_event(event_refs, ev))
+                .collect();
+            learned_generator(options, &event_hashes)
+        }
+        GenDef::Automaton(options, events) => {
+            let on = register_event(event_refs, &events[0]);
+            let off = match events.get(1) {
+                Some(ev) => register_event(event_refs, ev),
+                None => register_event(event_refs, &("~", Vec::new())),
+            };
+            automaton_generator(options, on, off)
+        }
+        GenDef::LSystem(axiom, rules, depth) => {
+            let axiom_hashes: Vec<EventHash> = axiom
+                .iter()
+                .map(|ev| register_event(event_refs, ev))
+                .collect();
+            let rule_hashes: Vec<(EventHash, Vec<EventHash>)> = rules
+                .iter()
+                .map(|(sym, rhs)| {
+                    (
+                        register_event(event_refs, sym),
+                        rhs.iter()
+                            .map(|ev| register_event(event_refs, ev))
+                            .collect(),
+                    )
+                })
+                .collect();
+            Box::new(LSystemSequenceGenerator::from_params(
+                &axiom_hashes,
+                &rule_hashes,
+                *depth,
+            ))
+        }
+    }
+}
+
+/// Create a parameter generator from its definition.
+fn param_generator(def: &GenDef<f32>, state: usize) -> Box<dyn SequenceGenerator<N32, usize>> {
+    match def {
+        GenDef::Items(func_name, values) => {
+            let param_conv: Vec<N32> = values.iter().map(|raw_float| (*raw_float).into()).collect();
+
+            match *func_name {
+                "rnd" => Box::new(RandomSequenceGenerator::from_seq(&param_conv)),
+                "shuffle" => Box::new(ShuffleSequenceGenerator::from_seq(&param_conv)),
+                "norepeat" => Box::new(NoRepeatSequenceGenerator::from_seq(&param_conv)),
+                "cyc" => Box::new(CycleSequenceGenerator::from_seq_with_index(
+                    &param_conv,
+                    state,
+                )),
+                // the parser makes sure these come with three values
+                "bounce" => Box::new(BounceSequenceGenerator::from_params(
+                    param_conv[0],
+                    param_conv[1],
+                    param_conv[2],
+                )),
+                "ramp" => Box::new(RampSequenceGenerator::from_params(
+                    param_conv[0],
+                    param_conv[1],
+                    param_conv[2],
+                )),
+                "sine" | "tri" | "saw" | "square" | "sah" => {
+                    Box::new(LfoSequenceGenerator::from_params(
+                        LfoShape::from_name(func_name).unwrap(),
+                        param_conv[0],
+                        param_conv[1],
+                        param_conv[2],
+                    ))
+                }
+                //"brownian" => Box::new(BounceSequenceGenerator::from_params(param_conv[0], param_conv[1], param_conv[2])),
+                _ => Box::new(CycleSequenceGenerator::from_seq(&param_conv)),
+            }
+        }
+        GenDef::Markov(transitions) => {
+            let converted: Vec<(N32, N32, f32)> = transitions
+                .iter()
+                .map(|(src, dst, prob)| ((*src).into(), (*dst).into(), *prob))
+                .collect();
+            let sources: Vec<N32> = converted.iter().map(|t| t.0).collect();
+            checked_or_fallback(
+                "markov chain",
+                MarkovSequenceGenerator::from_transitions(&converted),
+                &sources,
+            )
+        }
+        GenDef::Infer(rules) => {
+            let converted: Vec<(Vec<N32>, N32, f32)> = rules
+                .iter()
+                .map(|(source, symbol, prob)| {
+                    (
+                        source.iter().map(|raw_float| (*raw_float).into()).collect(),
+                        (*symbol).into(),
+                        *prob,
+                    )
+                })
+                .collect();
+            inferred_generator(&converted)
+        }
+        GenDef::Learn(options, values) => {
+            let param_conv: Vec<N32> = values.iter().map(|raw_float| (*raw_float).into()).collect();
+            learned_generator(options, &param_conv)
+        }
+        GenDef::Automaton(options, values) => {
+            let on: N32 = values[0].into();
+            let off: N32 = values.get(1).copied().unwrap_or(0.0).into();
+            automaton_generator(options, on, off)
+        }
+        GenDef::LSystem(axiom, rules, depth) => {
+            let axiom_conv: Vec<N32> = axiom.iter().map(|raw_float| (*raw_float).into()).collect();
+            let rules_conv: Vec<(N32, Vec<N32>)> = rules
+                .iter()
+                .map(|(sym, rhs)| {
+                    (
+                        (*sym).into(),
+                        rhs.iter().map(|raw_float| (*raw_float).into()).collect(),
+                    )
+                })
+                .collect();
+            Box::new(LSystemSequenceGenerator::from_params(
+                &axiom_conv,
+                &rules_conv,
+                *depth,
+            ))
+        }
+    }
+}
+
+/// Create a parameter generator from a (possibly combined) expression.
+/// The target parameter decides whether pitch generators deliver note numbers or frequencies.
+fn param_expr_generator(
+    expr: &ParamExpr,
+    target: &str,
+    state: usize,
+) -> Box<dyn SequenceGenerator<N32, usize>> {
+    match expr {
+        ParamExpr::Gen(def) => param_generator(def, state),
+        ParamExpr::Envelope(points, looping) => {
+            Box::new(EnvelopeSequenceGenerator::from_points(points, *looping))
+        }
+        ParamExpr::Arp(mode_name, octaves, notes) => {
+            let mode = ArpMode::from_name(mode_name).unwrap_or(ArpMode::Up);
+            Box::new(ArpSequenceGenerator::from_notes(
+                notes,
+                mode,
+                *octaves,
+                target == "freq",
+            ))
+        }
+        ParamExpr::Mul(left, right) => Box::new(BinarySequenceGenerator::from_generators(
+            BinaryOp::Mul,
+            param_expr_generator(left, target, state),
+            param_expr_generator(right, target, state),
+        )),
+        ParamExpr::Add(left, right) => Box::new(BinarySequenceGenerator::from_generators(
+            BinaryOp::Add,
+            param_expr_generator(left, target, state),
+            param_expr_generator(right, target, state),
+        )),
+        ParamExpr::Groove(_) => unreachable!("the line groove isn't a parameter"),
+        ParamExpr::Modified(name, args, inner) => {
+            let inner_gen = param_expr_generator(inner, target, state);
+            match Modifier::from_parsed(name, args) {
+                Ok(modifier) => Box::new(ModifiedSequenceGenerator::from_generator(
+                    modifier, inner_gen,
+                )),
+                Err(err) => {
+                    log!("invalid modifier: {}", err);
+                    inner_gen
+                }
+            }
+        }
+    }
+}
+
+/// Create a cellular automaton, using the given options where present.
+fn automaton_generator<T: Copy + 'static>(
+    options: &[(&str, u64)],
+    on: T,
+    off: T,
+) -> Box<dyn SequenceGenerator<T, usize>> {
+    let mut rule = 30;
+    let mut width = 16;
+    let mut seed = None;
+    for (name, value) in options.iter() {
+        match *name {
+            "rule" => rule = (*value).min(255) as u8,
+            "width" => width = *value as usize,
+            "seed" => seed = Some(*value),
+            _ => {}
+        }
+    }
+    if width > CA_MAX_WIDTH {
+        log!("automaton width {} limited to {}", width, CA_MAX_WIDTH);
+    }
+    Box::new(CellularAutomatonSequenceGenerator::from_params(
+        on, off, rule, width, seed,
+    ))
+}
+
+/// Learn a PFA from the example sequence, using the given options where present.
+fn learned_generator<T: Eq + Copy + Hash + Ord + std::fmt::Debug + 'static>(
+    options: &[(&str, f32)],
+    seq: &[T],
+) -> Box<dyn SequenceGenerator<T, usize>> {
+    let mut params = PfaLearnParams::default();
+    for (name, value) in options.iter() {
+        match *name {
+            "order" => params.order = *value as usize,
+            "bound" => params.bound = *value,
+            "history" => params.history = *value as usize,
+            _ => {}
+        }
+    }
+
+    let learned = params
+        .validate()
+        .map(|_| PfaSequenceGenerator::from_seq_with_params(seq, &params));
+    if let Ok(gen) = &learned {
+        log!(
+            "learned automaton with {} states from {} items",
+            gen.size(),
+            seq.len()
+        );
+    }
+    checked_or_fallback("learn options", learned, seq)
+}
+
+/// Infer a PFA from the explicit rules.
+fn inferred_generator<T: Eq + Copy + Hash + Ord + std::fmt::Debug + 'static>(
+    rules: &[(Vec<T>, T, f32)],
+) -> Box<dyn SequenceGenerator<T, usize>> {
+    let symbols: Vec<T> = rules.iter().map(|r| r.1).collect();
+    checked_or_fallback(
+        "rule set",
+        PfaSequenceGenerator::from_rules(rules),
+        &symbols,
+    )
+}
+
+/// Invalid definitions are reported, the line then just cycles through the fallback items.
+fn checked_or_fallback<T: Copy + 'static, G: SequenceGenerator<T, usize> + 'static>(
+    kind: &str,
+    checked: Result<G, String>,
+    fallback: &[T],
+) -> Box<dyn SequenceGenerator<T, usize>> {
+    match checked {
+        Ok(gen) => Box::new(gen),
+        Err(err) => {
+            log!("invalid {}: {}", kind, err);
+            Box::new(CycleSequenceGenerator::from_seq(fallback))
+        }
+    }
+}
+
 /// A simple event sequence represented by a vector of strings and params
+#[derive(Clone)]
 struct EventSequence {
     event_refs: HashMap<EventHash, MainEvent>,
     events: Box<dyn SequenceGenerator<EventHash, usize>>,
     param_generators: HashMap<String, Box<dyn SequenceGenerator<N32, usize>>>,
+    /// the most recent events, with the parameter values they were generated with
+    history: VecDeque<(String, HashMap<String, f32>)>,
+    /// name of the groove of this line, if any
+    groove: Option<String>,
+    /// (events, ticks), the line plays that many events in that many ticks
+    rate: (u64, u64),
+    /// whether the last event was the last of the cycle
+    wrapped: bool,
+    /// position of the next event within the current cycle
+    step: usize,
+    /// cycles completed so far
+    cycles: u64,
+    /// line in the code this sequence was built from
+    source_line: usize,
+    /// event hashes and column spans of the events as written in the pattern
+    tokens: Vec<(EventHash, (usize, usize))>,
+    /// where the last event came from
+    source: EventSource,
 }
 
 impl EventSequence {
-    /// Create an event sequence from a string.    
-    #[allow(clippy::type_complexity)]
-    pub fn from_parsed_line_ast(
-        input_line: (
-            (&str, Vec<(&str, Vec<(&str, f32)>)>),
-            Vec<((&str, &str), Vec<f32>)>,
-        ),
-    ) -> Self {
-        let pattern_ast = input_line.0;
-        let param_asts = input_line.1;
+    /// Create an event sequence from a parsed line.
+    pub fn from_parsed_line_ast(input_line: PatternLine) -> Self {
+        let (pattern_ast, param_asts) = input_line;
 
         let mut main_events = HashMap::new();
-        let mut event_hashes = Vec::new();
-
-        for parsed_event in pattern_ast.1.iter() {
-            let main_event =
-                MainEvent::from_parsed_input(parsed_event.0.to_string(), &parsed_event.1);
-            let main_event_hash = calculate_hash::<MainEvent>(&main_event);
-            main_events.insert(main_event_hash, main_event);
-            event_hashes.push(main_event_hash);
-        }
+        let events = event_generator(&pattern_ast, &mut main_events, 0);
 
         let mut param_row_map: HashMap<String, Box<dyn SequenceGenerator<N32, usize>>> =
             HashMap::new();
 
-        for parsed_param_seq in param_asts.iter() {
-            let mut param_conv: Vec<N32> = Vec::new();
-            for raw_float in &parsed_param_seq.1 {
-                param_conv.push((*raw_float).into())
-            }
+        let mut groove = None;
 
+        for (param_name, param_def) in param_asts.iter() {
+            if let ParamExpr::Groove(name) = param_def {
+                groove = Some(name.to_string());
+                continue;
+            }
             param_row_map.insert(
-                (parsed_param_seq.0).0.to_string(),
-                match (parsed_param_seq.0).1 {
-                    "rnd" => Box::new(RandomSequenceGenerator::from_seq(&param_conv)),
-                    "cyc" => Box::new(CycleSequenceGenerator::from_seq(&param_conv)),
-                    "learn" => Box::new(PfaSequenceGenerator::from_seq(&param_conv)),
-                    "bounce" => Box::new(BounceSequenceGenerator::from_params(
-                        param_conv[0],
-                        param_conv[1],
-                        param_conv[2],
-                    )),
-                    "ramp" => Box::new(RampSequenceGenerator::from_params(
-                        param_conv[0],
-                        param_conv[1],
-                        param_conv[2],
-                    )),
-                    //"brownian" => Box::new(BounceSequenceGenerator::from_params(param_conv[0], param_conv[1], param_conv[2])),
-                    _ => Box::new(CycleSequenceGenerator::from_seq(&param_conv)),
-                },
+                param_name.to_string(),
+                param_expr_generator(param_def, param_name, 0),
             );
         }
 
         EventSequence {
             event_refs: main_events,
-            events: match pattern_ast.0 {
-                "rnd" => Box::new(RandomSequenceGenerator::from_seq(&event_hashes)),
-                "cyc" => Box::new(CycleSequenceGenerator::from_seq(&event_hashes)),
-                "learn" => Box::new(PfaSequenceGenerator::from_seq(&event_hashes)),
-                _ => Box::new(CycleSequenceGenerator::from_seq(&event_hashes)),
-            },
-
+            events,
             param_generators: param_row_map,
+            history: VecDeque::new(),
+            groove,
+            rate: (1, 1),
+            wrapped: false,
+            step: 0,
+            cycles: 0,
+            source_line: 0,
+            tokens: Vec::new(),
+            source: EventSource::default(),
         }
     }
 
-    /// Update an existing sequence from a string.
-    #[allow(clippy::type_complexity)]
-    pub fn update_sequence(
-        &mut self,
-        input_line: (
-            (&str, Vec<(&str, Vec<(&str, f32)>)>),
-            Vec<((&str, &str), Vec<f32>)>,
-        ),
-    ) {
-        self.event_refs.clear();
-        self.param_generators.clear();
-
-        let pattern_ast = input_line.0;
-        let param_asts = input_line.1;
+    /// Update an existing sequence from a parsed line, keeping the cycle positions.
+    pub fn update_sequence(&mut self, input_line: PatternLine) {
+        let (pattern_ast, param_asts) = input_line;
 
-        let mut main_events = HashMap::new();
-        let mut event_hashes = Vec::new();
+        let old_param_generators = std::mem::take(&mut self.param_generators);
+        self.groove = None;
 
-        //let mut param_row_map: HashMap<String, Box<dyn SequenceGenerator<N32>>> = HashMap::new();
-
-        for parsed_param_seq in param_asts.iter() {
-            let mut param_conv: Vec<N32> = Vec::new();
-            for raw_float in &parsed_param_seq.1 {
-                param_conv.push((*raw_float).into())
+        for (param_name, param_def) in param_asts.iter() {
+            if let ParamExpr::Groove(name) = param_def {
+                self.groove = Some(name.to_string());
+                continue;
             }
-
-            let key = (parsed_param_seq.0).0.to_string();
+            let key = param_name.to_string();
             let mut state = 0;
-            if self.param_generators.contains_key(&key) {
-                state = self.param_generators[&key].get_state();
+            if let Some(old_gen) = old_param_generators.get(&key) {
+                state = old_gen.get_state();
             }
 
-            self.param_generators.insert(
-                key,
-                match (parsed_param_seq.0).1 {
-                    "rnd" => Box::new(RandomSequenceGenerator::from_seq(&param_conv)),
-                    "cyc" => Box::new(CycleSequenceGenerator::from_seq_with_index(
-                        &param_conv,
-                        state,
-                    )),
-                    "learn" => Box::new(PfaSequenceGenerator::from_seq(&param_conv)),
-                    "bounce" => Box::new(BounceSequenceGenerator::from_params(
-                        param_conv[0],
-                        param_conv[1],
-                        param_conv[2],
-                    )),
-                    "ramp" => Box::new(RampSequenceGenerator::from_params(
-                        param_conv[0],
-                        param_conv[1],
-                        param_conv[2],
-                    )),
-                    //"brownian" => Box::new(BounceSequenceGenerator::from_params(param_conv[0], param_conv[1], param_conv[2])),
-                    _ => Box::new(CycleSequenceGenerator::from_seq(&param_conv)),
-                },
-            );
+            self.param_generators
+                .insert(key, param_expr_generator(param_def, param_name, state));
         }
 
-        for parsed_event in pattern_ast.1.iter() {
-            let main_event =
-                MainEvent::from_parsed_input(parsed_event.0.to_string(), &parsed_event.1);
-            let main_event_hash = calculate_hash::<MainEvent>(&main_event);
-            main_events.insert(main_event_hash, main_event);
-            event_hashes.push(main_event_hash);
+        self.event_refs.clear();
+
+        let cycle_state = self.events.get_state();
+        self.events = event_generator(&pattern_ast, &mut self.event_refs, cycle_state);
+        // only cycles keep their position, the others start a new cycle
+        self.step = self.events.get_state();
+    }
+
+    /// Offsets (as fraction of a tick) of the events of this line that fall into the given tick.
+    /// The steps are counted on the line's own grid.
+    fn steps_in_tick(&self, tick: u64) -> Vec<(u64, f64)> {
+        line_steps_in_tick(self.rate, tick)
+            .zip(steps_in_tick(self.rate, tick))
+            .collect()
+    }
+
+    /// Duration of one step of this line, in ticks.
+    fn step_ticks(&self) -> f64 {
+        self.rate.1 as f64 / self.rate.0 as f64
+    }
+
+    /// Column span of the token of an event, preferring the one at the current step
+    /// as the same event might appear several times.
+    fn token_span(&self, step: usize, hash: EventHash) -> Option<(usize, usize)> {
+        match self.tokens.get(step) {
+            Some((token_hash, span)) if *token_hash == hash => Some(*span),
+            _ => self
+                .tokens
+                .iter()
+                .find(|(token_hash, _)| *token_hash == hash)
+                .map(|(_, span)| *span),
         }
+    }
 
-        self.event_refs = main_events;
+    /// Advance over the given number of events without playing them. As long as all
+    /// generators are in the same state whenever they wrap together, whole periods
+    /// of the line are skipped rather than generated.
+    fn skip_events(&mut self, count: u64, time: f64) {
+        let repeats =
+            self.events.repeats() && self.param_generators.values().all(|gen| gen.repeats());
+        // events and cycles when the line last wrapped as a whole
+        let mut mark: Option<(u64, u64)> = None;
 
-        let cycle_state = self.events.get_state();
+        let mut skipped = 0;
+        while skipped < count {
+            self.get_next_event(time);
+            // the skipped events weren't played, so there's nothing to freeze
+            self.history.pop_back();
+            skipped += 1;
 
-        self.events = match pattern_ast.0 {
-            "rnd" => Box::new(RandomSequenceGenerator::from_seq(&event_hashes)),
-            "cyc" => Box::new(CycleSequenceGenerator::from_seq_with_index(
-                &event_hashes,
-                cycle_state,
-            )),
-            "learn" => Box::new(PfaSequenceGenerator::from_seq(&event_hashes)),
-            _ => Box::new(CycleSequenceGenerator::from_seq(&event_hashes)),
+            if !repeats || !self.wrapped || !self.param_generators.values().all(|gen| gen.wrapped())
+            {
+                continue;
+            }
+            match mark {
+                Some((mark_events, mark_cycles)) => {
+                    let period = skipped - mark_events;
+                    let periods = (count - skipped) / period;
+                    self.cycles += periods * (self.cycles - mark_cycles);
+                    skipped += periods * period;
+                    mark = Some((skipped, self.cycles));
+                }
+                None => mark = Some((skipped, self.cycles)),
+            }
+        }
+    }
+
+    /// get the next event in the sequence, scheduled at the given audio time
+    pub fn get_next_event(&mut self, time: f64) -> (String, HashMap<String, f32>) {
+        let step = self.step;
+        let (name, params, hash) = self.generate_event(time);
+
+        self.source = EventSource {
+            track: self.source.track,
+            line: self.source_line,
+            step,
+            span: hash.and_then(|hash| self.token_span(step, hash)),
+            cycle: self.cycles,
         };
+
+        self.wrapped = self.events.wrapped();
+        if self.wrapped {
+            self.step = 0;
+            self.cycles += 1;
+        } else {
+            self.step += 1;
+        }
+
+        if self.history.len() == FREEZE_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back((name.clone(), params.clone()));
+
+        (name, params)
     }
 
-    /// get the next event in the sequence
-    pub fn get_next_event(&mut self) -> (String, HashMap<String, f32>) {
+    fn generate_event(&mut self, time: f64) -> (String, HashMap<String, f32>, Option<EventHash>) {
         let mut final_param_map: HashMap<String, f32> = HashMap::new();
         match self.events.get_next() {
             Some(ev_hash) => {
                 let ev = &self.event_refs[&ev_hash];
                 if ev.name == "~" {
-                    return ("~".to_string(), final_param_map);
+                    return ("~".to_string(), final_param_map, Some(ev_hash));
                 }
                 // pref for dyn params, so insert fixed pars first (might be overwritten)
                 for (par, val) in ev.params.iter() {
@@ -248,28 +629,213 @@ impl EventSequence {
 
                 // pref for dyn params, so insert fixed pars first (might be overwritten)
                 for (par, gen) in self.param_generators.iter_mut() {
-                    match gen.get_next() {
+                    match gen.get_next_at(time) {
                         Some(val) => final_param_map.insert(par.to_string(), val.into()),
                         None => None,
                     };
                 }
 
                 // set some defaults
-                final_param_map.entry("pos".to_string()).or_insert(0.5);
+                for (par, val) in DEFAULT_PARAMS.iter() {
+                    final_param_map.entry(par.to_string()).or_insert(*val);
+                }
 
-                (ev.name.clone(), final_param_map)
+                (ev.name.clone(), final_param_map, Some(ev_hash))
             }
-            None => ("~".to_string(), final_param_map),
+            None => ("~".to_string(), final_param_map, None),
+        }
+    }
+}
+
+/// The lines of code that aren't empty or comments, trimmed,
+/// with their line number and the column they start at (in UTF-16 code units, like JS).
+fn code_lines(code: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    code.lines()
+        .enumerate()
+        .map(|(line_no, line)| {
+            let trimmed = line.trim_start();
+            let indent = &line[..line.len() - trimmed.len()];
+            (line_no, indent.encode_utf16().count(), trimmed.trim_end())
+        })
+        .filter(|(_, _, line)| !line.is_empty() && !line.starts_with('#'))
+}
+
+/// Hashes and column spans (in UTF-16 code units, like JS) of the events of a pattern,
+/// for highlighting, in the order they're written. Only for plain items, that's also
+/// the order they're played in, otherwise an event is found by its hash.
+/// The parsed events point into the line, which gives their position.
+fn token_spans(def: &GenDef<Event>, line: &str, column: usize) -> Vec<(EventHash, (usize, usize))> {
+    let events: Vec<&Event> = match def {
+        GenDef::Items(_, events) | GenDef::Automaton(_, events) | GenDef::Learn(_, events) => {
+            events.iter().collect()
         }
+        GenDef::Markov(transitions) => transitions
+            .iter()
+            .flat_map(|(src, dst, _)| [src, dst])
+            .collect(),
+        GenDef::Infer(rules) => rules
+            .iter()
+            .flat_map(|(history, symbol, _)| history.iter().chain(std::iter::once(symbol)))
+            .collect(),
+        GenDef::LSystem(axiom, rules, _) => axiom
+            .iter()
+            .chain(
+                rules
+                    .iter()
+                    .flat_map(|(symbol, rhs)| std::iter::once(symbol).chain(rhs.iter())),
+            )
+            .collect(),
+    };
+
+    events
+        .into_iter()
+        .map(|(name, params)| {
+            let start = name.as_ptr() as usize - line.as_ptr() as usize;
+            // events end at the next separator, l-system words are joined by "_"
+            let end = line[start..]
+                .char_indices()
+                .skip(1)
+                .find(|(_, c)| [' ', ',', ':', '_'].contains(c))
+                .map_or(line.len(), |(len, _)| start + len);
+            let event = MainEvent::from_parsed_input(name.to_string(), params);
+            let utf16_col = |byte_pos: usize| column + line[..byte_pos].encode_utf16().count();
+            (calculate_hash(&event), (utf16_col(start), utf16_col(end)))
+        })
+        .collect()
+}
+
+/// Write events back as a cycle, with all parameters explicit
+/// (except for those with default values).
+fn frozen_line(events: &[(String, HashMap<String, f32>)]) -> String {
+    let items: Vec<String> = events
+        .iter()
+        .map(|(name, params)| {
+            let mut pars: Vec<(&String, &f32)> = params
+                .iter()
+                .filter(|(par, val)| !DEFAULT_PARAMS.contains(&(par.as_str(), **val)))
+                .collect();
+            pars.sort_by(|a, b| a.0.cmp(b.0));
+
+            let mut item = name.clone();
+            for (par, val) in pars {
+                let value = match par.as_str() {
+                    "chord" => chord_name(*val).unwrap_or_else(|| "C".to_string()),
+                    _ => val.to_string(),
+                };
+                item.push_str(&format!(";{}={}", par, value));
+            }
+            item
+        })
+        .collect();
+
+    format!("cyc >> {}", items.join(" "))
+}
+
+/// Expand the chord parameter (if any) into one parameter set per chord tone.
+/// The voicing parameters are removed in any case, as the synths don't know them.
+fn expand_chord(
+    mut params: HashMap<String, f32>,
+    pitched: bool,
+    tuning: Option<&Tuning>,
+) -> Vec<HashMap<String, f32>> {
+    let chord = params.remove("chord").filter(|_| pitched);
+    let octave = params.remove("oct").unwrap_or(4.0) as i32;
+    let inversion = params.remove("inv").unwrap_or(0.0).max(0.0) as usize;
+    let spread = params.remove("spread").unwrap_or(0.0).max(0.0) as usize;
+
+    let notes = match chord {
+        Some(code) => chord_voicing(code, octave, inversion, spread),
+        None => Vec::new(),
+    };
+
+    if notes.is_empty() {
+        return vec![params];
+    }
+
+    // unmapped notes of the tuning are left out
+    notes
+        .iter()
+        .filter_map(|note| {
+            let freq = tuned_freq(*note, tuning)?;
+            let mut voice = params.clone();
+            voice.insert("freq".to_string(), freq);
+            Some(voice)
+        })
+        .collect()
+}
+
+/// Note number to frequency, in the given tuning or equal temperament.
+fn tuned_freq(note: f32, tuning: Option<&Tuning>) -> Option<f32> {
+    match tuning {
+        Some(tun) => tun.note_to_freq(note),
+        None => Some(note_to_freq(note)),
     }
 }
 
+/// Where an event comes from, so the editor can highlight it.
+#[derive(Clone, Copy, Debug, Default)]
+struct EventSource {
+    track: usize,
+    line: usize,
+    step: usize,
+    span: Option<(usize, usize)>,
+    cycle: u64,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TriggerData {
     pub params: HashMap<String, f32>,
     pub timestamp: f64,
     pub sample_id: String,
     pub source_type: String,
+    /// index of the pattern line (as used for preview and freeze)
+    pub track: usize,
+    /// line in the code
+    pub line: usize,
+    /// step within the pattern
+    pub step: usize,
+    /// start and end column of the event in the code line, if known
+    pub span: Option<(usize, usize)>,
+    /// number of cycles the line completed before this event
+    pub cycle: u64,
+}
+
+impl TriggerData {
+    /// Plain JS object, with the parameters as object rather than map.
+    fn to_js(&self) -> JsValue {
+        self.serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .unwrap()
+    }
+}
+
+/// What to do with the ticks missed while the scheduler was held up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CatchUp {
+    /// drop them, the lines advance silently to stay on the grid
+    Skip,
+    /// play them all, quickly one after another
+    Compress,
+    /// drop the time, the lines continue where they were
+    Resync,
+}
+
+impl CatchUp {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "skip" => Some(CatchUp::Skip),
+            "compress" => Some(CatchUp::Compress),
+            "resync" => Some(CatchUp::Resync),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the scheduler is running, keeping its position when paused.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transport {
+    Stopped,
+    Playing,
+    Paused,
 }
 
 /// A simple time-recursion event scheduler running at a fixed time interval.
@@ -282,9 +848,37 @@ pub struct Scheduler {
     audio_logical_time: f64,
     browser_logical_time: f64,
     lookahead: f64, // in seconds
-    tempo: f64,     // currently just the duration of a 16th note ...
+    /// if set, the lookahead follows the measured callback jitter
+    adaptive_lookahead: bool,
+    jitter: JitterTracker,
+    /// tempo, meter and position, one step per tick
+    clock: Clock,
+    /// the last tempo statement in the code, (bpm, optional ramp target and bars)
+    tempo_setting: Option<(f32, Option<(f32, u32)>)>,
+    /// when evaluated code takes effect
+    quantize: Quantize,
+    /// evaluated code waiting for the next quantize boundary
+    pending_code: Option<String>,
+    /// the code the sequences were built from, to rebuild them on reset
+    code: String,
+    transport: Transport,
+    catch_up: CatchUp,
+    /// missed ticks still to be played in compressed time
+    catch_up_ticks: u64,
+    compressed_tick_ms: f64,
+    dropped_ticks: u64,
     event_sequences: Vec<EventSequence>,
     event_variables: HashMap<String, MainEvent>,
+    /// if set, notes are scale degrees in this key (degrees without a key are in C major)
+    key: Option<Key>,
+    /// loaded scala tunings, by name
+    tunings: HashMap<String, Tuning>,
+    /// grooves defined in the code, by name
+    grooves: HashMap<String, Groove>,
+    /// if set, the groove for all lines that don't have their own
+    groove: Option<Groove>,
+    /// if set, notes are converted to frequencies in this tuning
+    tuning: Option<Tuning>,
 }
 
 // for clippy
@@ -303,127 +897,210 @@ impl Scheduler {
             audio_logical_time: 0.0,
             browser_logical_time: 0.0,
             lookahead: 0.100,
-            tempo: 128.0,
+            adaptive_lookahead: false,
+            jitter: JitterTracker::default(),
+            clock: Clock::default(),
+            tempo_setting: None,
+            quantize: Quantize::Off,
+            pending_code: None,
+            code: String::new(),
+            transport: Transport::Stopped,
+            catch_up: CatchUp::Skip,
+            catch_up_ticks: 0,
+            compressed_tick_ms: 0.0,
+            dropped_ticks: 0,
             event_sequences: Vec::new(),
             event_variables: HashMap::new(),
+            key: None,
+            tunings: HashMap::new(),
+            tuning: None,
+            grooves: HashMap::new(),
+            groove: None,
         }
     }
 
     /// Evaluate an input string, turn it into a series of event sequences.
-    pub fn evaluate(&mut self, input: Option<String>) {
-        match input {
-            Some(all_lines) => {
-                let mut seq_idx = 0;
-
-                for line in all_lines.lines() {
-                    let trimmed_line = line.trim();
-
-                    if !trimmed_line.is_empty() && !trimmed_line.starts_with('#') {
-                        // only these two for now ... could probably be solved more elegantly in the
-                        // parser itself ...
-                        match parser::variable_definiton(trimmed_line) {
-                            Ok(var_ast) => {
-                                let res = var_ast.1;
-                                let var_def = res.0;
-                                let ev_def = res.1;
-                                let name = var_def.1;
-                                let event =
-                                    MainEvent::from_parsed_input(ev_def.0.to_string(), &ev_def.1);
-                                self.event_variables.insert(name.to_string(), event);
-                            }
-                            Err(_) => {
-                                match parser::pattern_line(trimmed_line) {
-                                    Ok(pat_ast) => {
-                                        if self.event_sequences.len() > seq_idx {
-                                            self.event_sequences[seq_idx]
-                                                .update_sequence(pat_ast.1);
-                                        } else {
-                                            self.event_sequences.push(
-                                                EventSequence::from_parsed_line_ast(pat_ast.1),
-                                            );
-                                        }
-                                    }
-                                    Err(pat_err) => {
-                                        log!("invalid line! {:?}, {}", pat_err, trimmed_line)
-                                    } // ??
-                                };
-                                seq_idx += 1;
-                            }
-                        };
-                    }
-                }
+    /// The code is checked right away, but depending on the quantize setting
+    /// it might only take effect at the next boundary.
+    /// Freeze commands are one-shot, if there are any, the code with the commands
+    /// replaced by the frozen lines is returned for the editor.
+    pub fn evaluate(&mut self, input: Option<String>) -> Option<String> {
+        let all_lines = match input {
+            Some(all_lines) => all_lines,
+            None => {
+                log!("no input!");
+                return None;
+            }
+        };
 
-                // check if we need to remove some sequnces because the number of lines got reduced ...
-                if seq_idx < self.event_sequences.len() {
-                    self.event_sequences.truncate(seq_idx);
-                }
+        let mut frozen = HashMap::new();
+        for (line_no, _, line) in code_lines(&all_lines) {
+            match parser::statement(line) {
+                // commands take effect right away
+                Ok((_, Statement::Quantize(quantize))) => self.quantize = quantize,
+                Ok((_, Statement::Freeze(line, steps))) => match self.freeze(line, steps) {
+                    Some(text) => {
+                        frozen.insert(line_no, text);
+                    }
+                    None => log!("nothing to freeze in line {}!", line),
+                },
+                Ok(_) => {}
+                Err(err) => log!("invalid line! {:?}, {}", err, line),
             }
+        }
 
-            None => log!("no input!"),
+        let edited = if frozen.is_empty() {
+            None
+        } else {
+            let lines: Vec<&str> = all_lines
+                .lines()
+                .enumerate()
+                .map(|(line_no, line)| frozen.get(&line_no).map_or(line, |text| text.as_str()))
+                .collect();
+            Some(lines.join("\n"))
+        };
+
+        if self.quantize == Quantize::Off {
+            self.pending_code = None;
+            self.apply_code(&all_lines);
+        } else {
+            self.pending_code = Some(all_lines);
         }
+
+        edited
     }
 
     /// Fetch all events from the event sequences, pass then to the JS scheduler
     pub fn generate_events(&mut self) -> Vec<JsValue> {
-        let mut triggers = Vec::new();
-
-        let trigger_time = self.audio_logical_time + self.lookahead;
-
-        for seq in self.event_sequences.iter_mut() {
-            let (mut next_event, mut next_params) = seq.get_next_event();
-
-            // overwrite with variable if there is one ...
-            if self.event_variables.contains_key(&next_event) {
-                // first get params, then overwrite key
-                let var_params = self.event_variables[&next_event].get_raw_params();
-                for (k, v) in var_params {
-                    next_params.entry(k).or_insert(v);
-                }
-                next_event = self.event_variables[&next_event].name.clone();
-            }
+        // might not be the most efficient way to do this but I doubt that the old stdweb
+        // implementation was any faster ...
+        self.generate_triggers()
+            .iter()
+            .map(TriggerData::to_js)
+            .collect()
+    }
 
-            let next_source_type = match next_event.as_str() {
-                "sine" => "SineSynth",
-                "saw" => "LFSawSynth",
-                "sqr" => "LFSquareSynth",
-                _ => "Sampler",
-            };
+    /// The events of the next steps of a line, without advancing it.
+    /// Random choices in the preview won't necessarily match what's played later.
+    pub fn preview(&self, line: usize, steps: usize) -> Vec<JsValue> {
+        self.preview_triggers(line, steps)
+            .iter()
+            .map(TriggerData::to_js)
+            .collect()
+    }
 
-            // might not be the most efficient way to do this but I doubt that the old stdweb
-            // implementation was any faster ...
-            if next_event != "~" {
-                triggers.push(
-                    JsValue::from_serde(&TriggerData {
-                        params: next_params,
-                        timestamp: trigger_time,
-                        sample_id: next_event.to_string(),
-                        source_type: next_source_type.to_string(),
-                    })
-                    .unwrap(),
-                );
-            }
+    /// The last steps generated by a line, as a cycle with explicit parameters
+    /// that can be pasted back into the code.
+    pub fn freeze(&self, line: usize, steps: usize) -> Option<String> {
+        let history = &self.event_sequences.get(line)?.history;
+        if history.is_empty() || steps == 0 {
+            return None;
         }
-
-        triggers
+        let start = history.len().saturating_sub(steps);
+        let events: Vec<(String, HashMap<String, f32>)> =
+            history.iter().skip(start).cloned().collect();
+        Some(frozen_line(&events))
     }
 
     /// The main scheduler recursion.
     pub fn compensate_time(&mut self, browser_timestamp: f64) -> f64 {
+        // while catching up, the missed ticks follow each other right away,
+        // squeezed into a single tick
+        if self.catch_up_ticks > 0 {
+            self.catch_up_ticks -= 1;
+            self.advance_tick(self.compressed_tick_ms);
+            return 0.0;
+        }
+
+        let tick_ms = self.clock.tick_ms();
+        self.advance_tick(tick_ms);
+
+        // if the worker was throttled (background tab, GC pause), whole ticks might
+        // have passed already, which would otherwise all be generated late at once
+        let behind = browser_timestamp - self.browser_logical_time;
+        let missed = (behind / tick_ms).floor().max(0.0) as u64;
+        if missed > 0 {
+            match self.catch_up {
+                CatchUp::Skip => {
+                    self.skip_ticks(self.clock.total_steps(), missed);
+                    for _ in 0..missed {
+                        self.advance_tick(self.clock.tick_ms());
+                    }
+                    self.dropped_ticks += missed;
+                }
+                CatchUp::Compress => {
+                    self.resync(browser_timestamp);
+                    self.catch_up_ticks = missed;
+                    self.compressed_tick_ms = tick_ms / (missed + 1) as f64;
+                }
+                CatchUp::Resync => {
+                    self.resync(browser_timestamp);
+                    self.dropped_ticks += missed;
+                }
+            }
+            log!("scheduler fell behind by {} ticks!", missed);
+        } else if self.adaptive_lookahead {
+            // missed ticks are the catch-up policy's business, the lookahead
+            // only needs to cover the regular jitter
+            self.jitter.measure(behind + tick_ms);
+            self.lookahead = self.jitter.adapt(self.lookahead * 1000.0) / 1000.0;
+        }
+
         // Calculate drift, correct timing.
         // The time at which this is called is most likely later, but never earlier,
         // than the time it SHOULD have been called at (self.browser_logical_time).
         // To compensate for the delay, we schedule the next call a bit earlier
         // than the actual interval.
-        let next_schedule_time = self.tempo - (browser_timestamp - self.browser_logical_time);
+        self.browser_logical_time - browser_timestamp
+    }
 
-        // Advance timestamps!
-        // audio time in seconds
-        self.audio_logical_time += self.tempo / 1000.0;
+    /// Set what happens to missed ticks when the scheduler falls behind,
+    /// "skip", "compress" or "resync".
+    pub fn set_catch_up(&mut self, policy: String) -> bool {
+        match CatchUp::from_name(&policy) {
+            Some(catch_up) => {
+                self.catch_up = catch_up;
+                true
+            }
+            None => {
+                log!("unknown catch-up policy! {}", policy);
+                false
+            }
+        }
+    }
 
-        // browser time in milliseconds
-        self.browser_logical_time += self.tempo;
+    /// Set a fixed lookahead in milliseconds, which ends the adaptive mode.
+    pub fn set_lookahead(&mut self, lookahead_ms: f64) {
+        self.adaptive_lookahead = false;
+        self.lookahead = lookahead_ms.clamp(MIN_LOOKAHEAD_MS, MAX_LOOKAHEAD_MS) / 1000.0;
+    }
 
-        next_schedule_time
+    /// Current lookahead in milliseconds.
+    pub fn lookahead(&self) -> f64 {
+        self.lookahead * 1000.0
+    }
+
+    /// Let the lookahead widen when the callbacks come late, and shrink again
+    /// when the timing is stable, starting from the current value.
+    pub fn set_adaptive_lookahead(&mut self, adaptive: bool) {
+        self.adaptive_lookahead = adaptive;
+        self.jitter = JitterTracker::default();
+    }
+
+    /// Number of states of the automaton a line learned or inferred, if it has one.
+    /// Without a parameter name, it's the one of the events.
+    pub fn automaton_size(&self, line: usize, param: Option<String>) -> Option<u32> {
+        let seq = self.event_sequences.get(line)?;
+        let size = match param {
+            Some(name) => seq.param_generators.get(&name)?.automaton_size(),
+            None => seq.events.automaton_size(),
+        };
+        size.map(|size| size as u32)
+    }
+
+    /// Ticks dropped since start while catching up.
+    pub fn dropped_ticks(&self) -> u32 {
+        u32::try_from(self.dropped_ticks).unwrap_or(u32::MAX)
     }
 
     /// Start this scheduler.
@@ -432,10 +1109,1146 @@ impl Scheduler {
         self.browser_start_time = browser_timestamp;
         self.audio_logical_time = self.audio_start_time;
         self.browser_logical_time = self.browser_start_time;
+        self.clock.reset();
+        // the reset stopped the ramp of code evaluated before, which starts now
+        if let Some(setting) = self.tempo_setting {
+            self.apply_tempo(setting);
+        }
+        self.transport = Transport::Playing;
+        self.catch_up_ticks = 0;
+        self.dropped_ticks = 0;
+
+        // nothing to wait for if nothing is playing
+        if let Some(code) = self.pending_code.take() {
+            self.apply_code(&code);
+        }
+    }
+
+    /// Stop this scheduler (the timeout is cleared on the JS side).
+    pub fn stop(&mut self) {
+        self.transport = Transport::Stopped;
+    }
+
+    /// Pause, keeping the musical position and the state of all generators.
+    pub fn pause(&mut self) {
+        if self.transport == Transport::Playing {
+            self.transport = Transport::Paused;
+        }
+    }
+
+    /// Continue where it was paused, re-anchoring the logical time only.
+    /// Returns false if it wasn't paused.
+    pub fn resume(&mut self, audio_timestamp: f64, browser_timestamp: f64) -> bool {
+        if self.transport != Transport::Paused {
+            return false;
+        }
+        self.audio_logical_time = audio_timestamp;
+        self.browser_logical_time = browser_timestamp;
+        self.transport = Transport::Playing;
+        self.catch_up_ticks = 0;
+        true
+    }
+
+    /// Rewind all generators to their initial state, and the clock to the first bar.
+    pub fn reset(&mut self) {
+        self.seek(0, 0);
+    }
+
+    /// Jump to a position, up to MAX_SEEK_BARS. All lines are rebuilt and fast-forwarded,
+    /// so they stay in sync (random choices will differ, though).
+    pub fn seek(&mut self, bar: u32, step: u32) {
+        if let Some(code) = self.pending_code.take() {
+            self.code = code;
+        }
+        let steps_per_bar = self.clock.steps_per_bar();
+        let mut target = bar as u64 * steps_per_bar + step as u64;
+        if target > MAX_SEEK_BARS as u64 * steps_per_bar {
+            log!("can't seek beyond bar {}", MAX_SEEK_BARS);
+            target = MAX_SEEK_BARS as u64 * steps_per_bar;
+        }
+        // seeking stops the tempo ramp, the tempo statement starts it over from there
+        self.clock.seek(target);
+        self.tempo_setting = None;
+
+        let code = std::mem::take(&mut self.code);
+        self.event_sequences.clear();
+        self.apply_code(&code);
+        self.skip_ticks(0, target);
+    }
+
+    /// "stopped", "playing" or "paused"
+    pub fn transport_state(&self) -> String {
+        match self.transport {
+            Transport::Stopped => "stopped",
+            Transport::Playing => "playing",
+            Transport::Paused => "paused",
+        }
+        .to_string()
+    }
+
+    /// Steps since start, for position counters.
+    pub fn current_position(&self) -> u32 {
+        u32::try_from(self.clock.total_steps()).unwrap_or(u32::MAX)
+    }
+
+    /// Set tick duration in milliseconds (the tempo in bpm follows from the steps per beat).
+    pub fn set_tempo(&mut self, tempo: f64) -> bool {
+        if !(tempo.is_finite() && tempo > 0.0) {
+            log!("invalid tick duration! {}", tempo);
+            return false;
+        }
+        self.clock.set_tick_ms(tempo);
+        true
+    }
+
+    /// Set the tempo in beats per minute.
+    pub fn set_bpm(&mut self, bpm: f64) -> bool {
+        if !(bpm.is_finite() && bpm > 0.0) {
+            log!("invalid tempo! {}", bpm);
+            return false;
+        }
+        self.clock.set_bpm(bpm);
+        true
+    }
+
+    /// Set the meter, the tick duration is derived from the steps per beat.
+    pub fn set_meter(&mut self, steps_per_beat: u32, beats_per_bar: u32) -> bool {
+        if steps_per_beat == 0 || beats_per_bar == 0 {
+            log!("invalid meter! {} {}", steps_per_beat, beats_per_bar);
+            return false;
+        }
+        self.clock.set_meter(steps_per_beat, beats_per_bar);
+        true
+    }
+
+    pub fn bpm(&self) -> f64 {
+        self.clock.bpm
+    }
+
+    /// Duration of one tick (step) in milliseconds.
+    pub fn tick_duration(&self) -> f64 {
+        self.clock.tick_ms()
+    }
+
+    /// Current bar, counting from 0.
+    pub fn current_bar(&self) -> u32 {
+        u32::try_from(self.clock.bar()).unwrap_or(u32::MAX)
+    }
+
+    /// Current beat within the bar, counting from 0.
+    pub fn current_beat(&self) -> u32 {
+        self.clock.beat()
+    }
+
+    /// Current step within the beat, counting from 0.
+    pub fn current_step(&self) -> u32 {
+        self.clock.step_in_beat()
+    }
+
+    /// Load a scala tuning (.scl text, optionally with a .kbm keyboard mapping)
+    /// that can be selected in the code with "tuning <name>".
+    pub fn load_tuning(&mut self, name: String, scl: String, kbm: Option<String>) -> bool {
+        let scale = match parse_scl(&scl) {
+            Ok(scale) => scale,
+            Err(err) => {
+                log!("invalid scale file! {}: {}", name, err);
+                return false;
+            }
+        };
+
+        let mut tuning = Tuning::from_scale(scale);
+        if let Some(kbm_text) = kbm {
+            match parse_kbm(&kbm_text) {
+                Ok(mapping) => tuning.mapping = mapping,
+                Err(err) => {
+                    log!("invalid keyboard mapping! {}: {}", name, err);
+                    return false;
+                }
+            }
+        }
+
+        self.tunings.insert(name, tuning);
+        true
+    }
+}
+
+impl Scheduler {
+    /// The triggers of the next tick, applying pending code first if it's time.
+    fn generate_triggers(&mut self) -> Vec<TriggerData> {
+        if self.pending_code.is_some() && self.at_quantize_boundary() {
+            if let Some(code) = self.pending_code.take() {
+                self.apply_code(&code);
+            }
+        }
+
+        let trigger_time = self.audio_logical_time + self.lookahead;
+
+        let step = self.clock.total_steps();
+        let tick_secs = self.clock.tick_ms() / 1000.0;
+
+        // lines with a different rate might have no or several events in this tick
+        let mut next_events = Vec::new();
+        for seq in self.event_sequences.iter_mut() {
+            let step_secs = tick_secs * seq.step_ticks();
+            for (line_step, offset) in seq.steps_in_tick(step) {
+                let event_time = trigger_time + offset * tick_secs;
+                let (name, params) = seq.get_next_event(event_time);
+                let timing = (seq.groove.clone(), line_step, step_secs);
+                next_events.push((name, params, timing, event_time, seq.source));
+            }
+        }
+
+        // lines can sequence the tempo, which changes from the next tick on
+        for (_, params, _, _, _) in next_events.iter() {
+            match params.get("bpm") {
+                Some(bpm) if bpm.is_finite() && *bpm > 0.0 => self.clock.set_bpm(*bpm as f64),
+                _ => {}
+            }
+        }
+
+        next_events
+            .into_iter()
+            .flat_map(|(name, mut params, timing, event_time, source)| {
+                let (groove, line_step, step_secs) = timing;
+                let time = self.apply_timing(
+                    groove.as_deref(),
+                    (line_step, step_secs),
+                    &mut params,
+                    event_time,
+                );
+                self.resolve_event(name, params, time, source)
+            })
+            .collect()
+    }
+
+    /// Turn the code into event sequences and settings, errors have been reported before.
+    /// Set the tempo from a tempo statement, starting its ramp (if any) with the next step.
+    fn apply_tempo(&mut self, (bpm, ramp): (f32, Option<(f32, u32)>)) {
+        match ramp {
+            Some((to, bars)) => self.clock.ramp_bpm(bpm as f64, to as f64, bars),
+            None => self.clock.set_bpm(bpm as f64),
+        }
+    }
+
+    fn apply_code(&mut self, code: &str) {
+        let mut seq_idx = 0;
+        self.code = code.to_string();
+
+        // the key, tuning and groove only hold as long as they're in the code
+        self.key = None;
+        self.tuning = None;
+        self.groove = None;
+
+        for (line_no, column, line) in code_lines(code) {
+            // invalid lines are skipped, they've been reported when evaluating
+            let statement = match parser::statement(line) {
+                Ok((_, statement)) => statement,
+                Err(_) => continue,
+            };
+
+            match statement {
+                Statement::Variable(name, (event_name, params)) => {
+                    let event = MainEvent::from_parsed_input(event_name.to_string(), &params);
+                    self.event_variables.insert(name.to_string(), event);
+                }
+                Statement::Key(root, scale_name) => {
+                    self.key = Key::from_parsed(root, scale_name);
+                    if self.key.is_none() {
+                        log!("unknown scale! {}", scale_name);
+                    }
+                }
+                Statement::Tempo(bpm, ramp) => {
+                    // only a changed statement changes the tempo, otherwise
+                    // every evaluation would restart the ramp
+                    let setting = (bpm, ramp);
+                    if self.tempo_setting != Some(setting) {
+                        self.apply_tempo(setting);
+                        self.tempo_setting = Some(setting);
+                    }
+                }
+                // handled when evaluating
+                Statement::Quantize(_) | Statement::Freeze(_, _) => {}
+                Statement::GrooveDefinition(name, steps) => match Groove::from_steps(&steps) {
+                    Ok(groove) => {
+                        self.grooves.insert(name.to_string(), groove);
+                    }
+                    Err(err) => log!("invalid groove {}: {}", name, err),
+                },
+                Statement::GrooveSelection(name) => {
+                    self.groove = self.find_groove(name);
+                    if self.groove.is_none() {
+                        log!("unknown groove! {}", name);
+                    }
+                }
+                Statement::Swing(percent) => self.groove = Some(Groove::swing(percent)),
+                Statement::Tuning(name) => {
+                    self.tuning = self.tunings.get(name).cloned();
+                    if self.tuning.is_none() {
+                        log!("unknown tuning! {}", name);
+                    }
+                }
+                Statement::Pattern(rate, pattern) => {
+                    let tokens = token_spans(&pattern.0, line, column);
+                    let seq = if seq_idx < self.event_sequences.len() {
+                        let seq = &mut self.event_sequences[seq_idx];
+                        seq.update_sequence(pattern);
+                        seq
+                    } else {
+                        self.event_sequences
+                            .push(EventSequence::from_parsed_line_ast(pattern));
+                        self.event_sequences.last_mut().unwrap()
+                    };
+                    seq.rate = rate;
+                    seq.source_line = line_no;
+                    seq.tokens = tokens;
+                    seq.source.track = seq_idx;
+                    seq_idx += 1;
+                }
+            }
+        }
+
+        // check if we need to remove some sequnces because the number of lines got reduced ...
+        if seq_idx < self.event_sequences.len() {
+            self.event_sequences.truncate(seq_idx);
+        }
+
+        // custom grooves might be defined after the lines using them
+        for seq in self.event_sequences.iter() {
+            if let Some(name) = &seq.groove {
+                if self.find_groove(name).is_none() {
+                    log!("unknown groove! {}", name);
+                }
+            }
+        }
+    }
+
+    /// Advance the logical time and the clock by one tick.
+    fn advance_tick(&mut self, tick_ms: f64) {
+        // Advance timestamps!
+        // audio time in seconds
+        self.audio_logical_time += tick_ms / 1000.0;
+
+        // browser time in milliseconds
+        self.browser_logical_time += tick_ms;
+
+        self.clock.advance();
+    }
+
+    /// Move the logical time to now, the musical position stays the same.
+    fn resync(&mut self, browser_timestamp: f64) {
+        self.audio_logical_time += (browser_timestamp - self.browser_logical_time) / 1000.0;
+        self.browser_logical_time = browser_timestamp;
+    }
+
+    /// Advance all lines over the given ticks without playing anything, so they stay in sync.
+    fn skip_ticks(&mut self, from: u64, count: u64) {
+        for seq in self.event_sequences.iter_mut() {
+            let events = line_steps_in_tick(seq.rate, from).start
+                ..line_steps_in_tick(seq.rate, from + count).start;
+            seq.skip_events(events.end - events.start, self.audio_logical_time);
+        }
+    }
+
+    /// Whether pending code can be swapped in before the next tick.
+    fn at_quantize_boundary(&self) -> bool {
+        match self.quantize {
+            Quantize::Off => true,
+            Quantize::Bars(bars) => self.clock.at_bar_boundary(bars),
+            Quantize::Line(line) => self.event_sequences.get(line).is_none_or(|seq| seq.wrapped),
+        }
+    }
+
+    /// Turn the raw next event of a sequence into the triggers to send,
+    /// resolving variables, keys, tunings and chords.
+    fn resolve_event(
+        &self,
+        mut next_event: String,
+        mut next_params: HashMap<String, f32>,
+        trigger_time: f64,
+        source: EventSource,
+    ) -> Vec<TriggerData> {
+        // overwrite with variable if there is one ...
+        if self.event_variables.contains_key(&next_event) {
+            // first get params, then overwrite key
+            let var_params = self.event_variables[&next_event].get_raw_params();
+            for (k, v) in var_params {
+                next_params.entry(k).or_insert(v);
+            }
+            next_event = self.event_variables[&next_event].name.clone();
+        }
+
+        let next_source_type = match next_event.as_str() {
+            "sine" => "SineSynth",
+            "saw" => "LFSawSynth",
+            "sqr" => "LFSquareSynth",
+            _ => "Sampler",
+        };
+
+        // control events only carry parameters for the scheduler
+        next_params.remove("bpm");
+        if next_event == "~" || next_event == "ctrl" {
+            return Vec::new();
+        }
+
+        // scale degrees are turned into notes in the key (or C major without one) ...
+        // in a key, notes are scale degrees as well
+        let mut degree = next_params.remove("degree");
+        if degree.is_none() && self.key.is_some() {
+            degree = next_params.remove("note");
+        }
+        if let Some(degree) = degree {
+            let key = self.key.or_else(|| Key::from_parsed(0, "major"));
+            if let Some(key) = key {
+                let octave = next_params.get("oct").copied().unwrap_or(4.0) as i32;
+                next_params.insert("note".to_string(), key.degree_to_note(degree, octave));
+            }
+        }
+
+        // ... and notes into frequencies, in the tuning if there is one
+        // (unmapped notes are skipped)
+        if let Some(note) = next_params.remove("note") {
+            match tuned_freq(note, self.tuning.as_ref()) {
+                Some(freq) => next_params.insert("freq".to_string(), freq),
+                None => return Vec::new(),
+            };
+        }
+
+        // chords only make sense for the synths, one voice per chord tone
+        expand_chord(
+            next_params,
+            next_source_type != "Sampler",
+            self.tuning.as_ref(),
+        )
+        .into_iter()
+        .map(|voice_params| TriggerData {
+            params: voice_params,
+            timestamp: trigger_time,
+            sample_id: next_event.to_string(),
+            source_type: next_source_type.to_string(),
+            track: source.track,
+            line: source.line,
+            step: source.step,
+            span: source.span,
+            cycle: source.cycle,
+        })
+        .collect()
+    }
+
+    /// Look up a groove by name, custom ones first.
+    fn find_groove(&self, name: &str) -> Option<Groove> {
+        self.grooves
+            .get(name)
+            .cloned()
+            .or_else(|| Groove::builtin(name))
+    }
+
+    /// Shift an event by its swing parameter, the groove of its line or the global groove
+    /// (in that order), scale its level accordingly, add the nudge and return the new time.
+    /// The step is the one of the line, with its duration in seconds, as lines can run
+    /// at their own rate.
+    fn apply_timing(
+        &self,
+        line_groove: Option<&str>,
+        (step, step_secs): (u64, f64),
+        params: &mut HashMap<String, f32>,
+        trigger_time: f64,
+    ) -> f64 {
+        let (offset, level) = match params.remove("swing") {
+            Some(percent) if step % 2 == 1 => (swing_offset(percent), 1.0),
+            Some(_) => (0.0, 1.0),
+            None => line_groove
+                .and_then(|name| self.find_groove(name))
+                .or_else(|| self.groove.clone())
+                .map_or((0.0, 1.0), |groove| groove.at(step)),
+        };
+
+        // a missing level counts as full level
+        if level != 1.0 {
+            *params.entry("lvl".to_string()).or_insert(1.0) *= level;
+        }
+
+        // micro-timing per event, in milliseconds and/or fractions of a step,
+        // as far as the lookahead allows
+        let nudge = params.remove("nudge").unwrap_or(0.0) as f64 / 1000.0
+            + params.remove("nudgestep").unwrap_or(0.0) as f64 * step_secs;
+        let nudge = nudge.clamp(-self.lookahead, self.lookahead);
+
+        // early events can't be earlier than now
+        let time = trigger_time + offset as f64 * step_secs + nudge;
+        time.max(self.audio_logical_time)
+    }
+
+    /// Run a copy of a line for the given number of steps, starting at the next tick.
+    fn preview_triggers(&self, line: usize, steps: usize) -> Vec<TriggerData> {
+        let mut seq = match self.event_sequences.get(line) {
+            Some(seq) => seq.clone(),
+            None => return Vec::new(),
+        };
+
+        // follow tempo ramps on a copy of the clock
+        let mut clock = self.clock.clone();
+        let mut tick_time = self.audio_logical_time + self.lookahead;
+        let mut triggers = Vec::new();
+        let mut count = 0;
+        let mut tick = 0;
+
+        // the line might skip ticks, but never more than its rate says
+        while count < steps && tick <= steps as u64 * seq.rate.1 {
+            let step = clock.total_steps();
+            let tick_secs = clock.tick_ms() / 1000.0;
+            let step_secs = tick_secs * seq.step_ticks();
+            for (line_step, offset) in seq.steps_in_tick(step).into_iter().take(steps - count) {
+                let trigger_time = tick_time + offset * tick_secs;
+                let (name, mut params) = seq.get_next_event(trigger_time);
+                let time = self.apply_timing(
+                    seq.groove.as_deref(),
+                    (line_step, step_secs),
+                    &mut params,
+                    trigger_time,
+                );
+                triggers.append(&mut self.resolve_event(name, params, time, seq.source));
+                count += 1;
+            }
+            tick_time += tick_secs;
+            clock.advance();
+            tick += 1;
+        }
+        triggers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler_with(code: &str) -> Scheduler {
+        let mut sched = Scheduler::new();
+        sched.evaluate(Some(code.to_string()));
+        sched
+    }
+
+    /// The triggers of the next ticks, as if the scheduler was called right on time.
+    fn run_ticks(sched: &mut Scheduler, ticks: usize) -> Vec<TriggerData> {
+        let mut triggers = Vec::new();
+        for _ in 0..ticks {
+            triggers.append(&mut sched.generate_triggers());
+            let next_call = sched.browser_logical_time + sched.clock.tick_ms();
+            sched.compensate_time(next_call);
+        }
+        triggers
+    }
+
+    fn freqs(triggers: &[TriggerData]) -> Vec<f32> {
+        triggers
+            .iter()
+            .map(|trigger| trigger.params["freq"])
+            .collect()
+    }
+
+    #[test]
+    fn test_param_generator_values() {
+        let mut empty_gen = param_generator(&GenDef::Items("cyc", vec![]), 3);
+        assert_eq!(empty_gen.get_next(), None);
+
+        let mut ramp_gen = param_generator(&GenDef::Items("ramp", vec![0.0, 2.0, 2.0]), 0);
+        assert_eq!(ramp_gen.get_next(), Some((0.0).into()));
+        assert_eq!(ramp_gen.get_next(), Some((1.0).into()));
+    }
+
+    #[test]
+    fn test_notes_and_degrees() {
+        // in a key, notes are scale degrees
+        let mut sched = scheduler_with("key d dorian\ncyc >> sine @note: cyc >> 0 1 7");
+        let expected: Vec<f32> = [62.0, 64.0, 74.0].map(note_to_freq).to_vec();
+        assert_eq!(freqs(&run_ticks(&mut sched, 3)), expected);
+        let mut sched = scheduler_with("key d dorian\ncyc >> sine @degree: cyc >> 0 1 7");
+        assert_eq!(freqs(&run_ticks(&mut sched, 3)), expected);
+
+        // so random melodies stay in key
+        let mut sched = scheduler_with("key d dorian\ncyc >> sine @note: rnd >> 0 1 2 3 4 5 6");
+        let in_key: Vec<f32> = [62.0, 64.0, 65.0, 67.0, 69.0, 71.0, 72.0]
+            .map(note_to_freq)
+            .to_vec();
+        assert!(freqs(&run_ticks(&mut sched, 20))
+            .iter()
+            .all(|freq| in_key.contains(freq)));
+
+        // arpeggios over note numbers can play frequencies instead, no matter the key
+        let mut sched = scheduler_with("key d dorian\ncyc >> sine @freq: arp >> 60 64 67");
+        let expected: Vec<f32> = [60.0, 64.0, 67.0, 60.0].map(note_to_freq).to_vec();
+        assert_eq!(freqs(&run_ticks(&mut sched, 4)), expected);
+
+        // and in C major without a key, while notes are converted anyway
+        let mut sched = scheduler_with("cyc >> sine;degree=2;oct=3 sine;note=69");
+        let triggers = run_ticks(&mut sched, 2);
+        assert_eq!(freqs(&triggers), [note_to_freq(52.0), 440.0]);
+        assert!(triggers
+            .iter()
+            .all(|trigger| !trigger.params.contains_key("note")));
+    }
+
+    #[test]
+    fn test_chord_arp() {
+        let mut sched = scheduler_with("cyc >> sine @note: arp down 2 >> Cm7");
+        let expected: Vec<f32> = [82.0, 79.0, 75.0, 72.0, 70.0, 67.0, 63.0, 60.0, 82.0]
+            .map(note_to_freq)
+            .to_vec();
+        assert_eq!(freqs(&run_ticks(&mut sched, 9)), expected);
+    }
+
+    #[test]
+    fn test_chord_voices() {
+        // every note of a chord is a voice of its own
+        let mut sched = scheduler_with("cyc >> saw;chord=Cm7");
+        let expected: Vec<f32> = [60.0, 63.0, 67.0, 70.0].map(note_to_freq).to_vec();
+        assert_eq!(freqs(&run_ticks(&mut sched, 1)), expected);
+
+        let mut sched = scheduler_with("cyc >> saw;chord=C;oct=3;inv=1;spread=1");
+        let expected: Vec<f32> = [52.0, 67.0, 60.0].map(note_to_freq).to_vec();
+        assert_eq!(freqs(&run_ticks(&mut sched, 1)), expected);
+
+        // chords can be sequenced like any other parameter
+        let mut sched = scheduler_with("cyc >> saw @chord: cyc >> Cm7 F");
+        let expected: Vec<f32> = [60.0, 63.0, 67.0, 70.0, 65.0, 69.0, 72.0]
+            .map(note_to_freq)
+            .to_vec();
+        assert_eq!(freqs(&run_ticks(&mut sched, 2)), expected);
+
+        // samples stay single events
+        let mut sched = scheduler_with("cyc >> bd;chord=Cm7");
+        assert_eq!(run_ticks(&mut sched, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_scala_tuning() {
+        let scl = include_str!("../fixtures/pythagorean.scl");
+        let pythagorean = Tuning::from_scale(parse_scl(scl).unwrap());
+        let mut sched = Scheduler::new();
+        assert!(sched.load_tuning("pyth".to_string(), scl.to_string(), None));
+        sched.evaluate(Some(
+            "tuning pyth\ncyc >> sine @note: cyc >> 60 67".to_string(),
+        ));
+        let expected: Vec<f32> = [60.0, 67.0]
+            .iter()
+            .map(|note| pythagorean.note_to_freq(*note).unwrap())
+            .collect();
+        assert_eq!(freqs(&run_ticks(&mut sched, 2)), expected);
+
+        // chords are tuned as well
+        sched.evaluate(Some("tuning pyth\ncyc >> sine;chord=C".to_string()));
+        let expected: Vec<f32> = [60.0, 64.0, 67.0]
+            .iter()
+            .map(|note| pythagorean.note_to_freq(*note).unwrap())
+            .collect();
+        assert_eq!(freqs(&run_ticks(&mut sched, 1)), expected);
+
+        // without the tuning line, it's back to equal temperament
+        sched.evaluate(Some("cyc >> sine @note: cyc >> 67".to_string()));
+        assert_eq!(freqs(&run_ticks(&mut sched, 1)), [note_to_freq(67.0)]);
+
+        // notes the keyboard mapping leaves out aren't played
+        assert!(sched.load_tuning(
+            "white".to_string(),
+            include_str!("../fixtures/diatonic.scl").to_string(),
+            Some(include_str!("../fixtures/white-keys.kbm").to_string()),
+        ));
+        sched.evaluate(Some(
+            "tuning white\ncyc >> sine @note: cyc >> 69 70".to_string(),
+        ));
+        assert_eq!(freqs(&run_ticks(&mut sched, 2)), [432.0]);
+
+        assert!(!sched.load_tuning("broken".to_string(), "broken\n 3\n 1.5\n".to_string(), None));
+    }
+
+    #[test]
+    fn test_bpm_line() {
+        let mut sched = scheduler_with("cyc >> ctrl @bpm: cyc >> 100 200");
+        let triggers = run_ticks(&mut sched, 1);
+        assert_eq!(sched.bpm(), 100.0);
+        // the tempo isn't sent along as a parameter
+        assert!(triggers
+            .iter()
+            .all(|trigger| !trigger.params.contains_key("bpm")));
+        run_ticks(&mut sched, 1);
+        assert_eq!(sched.bpm(), 200.0);
+        assert_eq!(sched.clock.tick_ms(), 75.0);
+        run_ticks(&mut sched, 1);
+        assert_eq!(sched.bpm(), 100.0);
+    }
+
+    #[test]
+    fn test_groove_follows_line_rate() {
+        let times = |code: &str, ticks| -> Vec<f64> {
+            let mut sched = scheduler_with(code);
+            run_ticks(&mut sched, ticks)
+                .iter()
+                .map(|trigger| trigger.timestamp - sched.lookahead)
+                .collect()
+        };
+        let tick = 0.125;
+        let swung = swing_offset(66.0) as f64;
+
+        // twice as fast, the second event of every tick is the offbeat
+        let expected = [0.0, 0.5 + 0.5 * swung, 1.0, 1.5 + 0.5 * swung];
+        let actual = times("swing 66\n*2 cyc >> bd", 2);
+        assert_eq!(actual.len(), expected.len());
+        for (time, expected) in actual.iter().zip(expected) {
+            assert!((time - expected * tick).abs() < 1e-9);
+        }
+
+        // half as fast, every other event of the line is swung by a step of its own
+        let expected = [0.0, 2.0 + 2.0 * swung, 4.0];
+        let actual = times("/2 cyc >> bd @swing: cyc >> 66", 5);
+        assert_eq!(actual.len(), expected.len());
+        for (time, expected) in actual.iter().zip(expected) {
+            assert!((time - expected * tick).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_quantize_line() {
+        let names = |triggers: Vec<TriggerData>| -> Vec<String> {
+            triggers
+                .into_iter()
+                .map(|trigger| trigger.sample_id)
+                .collect()
+        };
+
+        // lines without a cycle of their own wrap after as many events as they have items
+        for pattern in ["rnd", "shuffle"] {
+            let mut sched = scheduler_with(&format!("quantize line 0\n{} >> bd sn hh", pattern));
+            run_ticks(&mut sched, 1);
+            sched.evaluate(Some(format!("quantize line 0\n{} >> cp", pattern)));
+            let played = names(run_ticks(&mut sched, 3));
+            assert!(played[..2].iter().all(|name| name != "cp"));
+            assert_eq!(played[2], "cp");
+        }
+    }
+
+    #[test]
+    fn test_quantize_bar() {
+        let names = |triggers: Vec<TriggerData>| -> Vec<String> {
+            triggers
+                .into_iter()
+                .map(|trigger| trigger.sample_id)
+                .collect()
+        };
+
+        // the new code takes over at the start of the next bar
+        let mut sched = scheduler_with("quantize bar\ncyc >> bd");
+        run_ticks(&mut sched, 5);
+        sched.evaluate(Some("quantize bar\ncyc >> sn".to_string()));
+        let played = names(run_ticks(&mut sched, 12));
+        assert!(played[..11].iter().all(|name| name == "bd"));
+        assert_eq!(played[11], "sn");
+
+        // or every few bars
+        let mut sched = scheduler_with("quantize 2\ncyc >> bd");
+        run_ticks(&mut sched, 5);
+        sched.evaluate(Some("quantize 2\ncyc >> sn".to_string()));
+        let played = names(run_ticks(&mut sched, 28));
+        assert!(played[..27].iter().all(|name| name == "bd"));
+        assert_eq!(played[27], "sn");
+    }
+
+    #[test]
+    fn test_event_source() {
+        let steps = |triggers: &[TriggerData]| -> Vec<(usize, u64)> {
+            triggers
+                .iter()
+                .map(|trigger| (trigger.step, trigger.cycle))
+                .collect()
+        };
+
+        let mut sched = scheduler_with("cyc >> bd sn bd");
+        let triggers = run_ticks(&mut sched, 4);
+        assert_eq!(steps(&triggers), [(0, 0), (1, 0), (2, 0), (0, 1)]);
+        // the repeated item is highlighted where it is played
+        let spans: Vec<Option<(usize, usize)>> =
+            triggers.iter().map(|trigger| trigger.span).collect();
+        assert_eq!(
+            spans,
+            [Some((7, 9)), Some((10, 12)), Some((13, 15)), Some((7, 9))]
+        );
+
+        // columns are counted like in JS, and other generators get spans as well
+        let mut sched = scheduler_with("\u{3000}markov >> bd -> sn 1.0, sn -> bd;lpf=300 1.0");
+        let spans: Vec<Option<(usize, usize)>> = run_ticks(&mut sched, 3)
+            .iter()
+            .map(|trigger| trigger.span)
+            .collect();
+        assert_eq!(spans, [Some((11, 13)), Some((17, 19)), Some((31, 41))]);
+        let mut sched = scheduler_with("lsys >> axiom=bd rules=bd:bd_sn depth=1");
+        let spans: Vec<Option<(usize, usize)>> = run_ticks(&mut sched, 2)
+            .iter()
+            .map(|trigger| trigger.span)
+            .collect();
+        assert_eq!(spans, [Some((14, 16)), Some((29, 31))]);
+
+        // the order is random, but steps and cycles are counted all the same
+        let mut sched = scheduler_with("shuffle >> bd sn hh");
+        assert_eq!(
+            steps(&run_ticks(&mut sched, 7)),
+            [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1), (0, 2)]
+        );
+    }
+
+    #[test]
+    fn test_transport() {
+        let mut sched = scheduler_with("cyc >> bd sn hh @lpf: cyc >> 100 200 300 400");
+        assert_eq!(sched.transport_state(), "stopped");
+        assert!(!sched.resume(0.0, 0.0));
+        assert_eq!(sched.transport_state(), "stopped");
+        sched.start(0.0, 0.0);
+        assert_eq!(sched.transport_state(), "playing");
+        run_ticks(&mut sched, 5);
+
+        // pausing and resuming keeps the position
+        sched.pause();
+        assert_eq!(sched.transport_state(), "paused");
+        assert!(sched.resume(10.0, 10000.0));
+        assert_eq!(sched.transport_state(), "playing");
+        // only a paused scheduler can be resumed
+        assert!(!sched.resume(20.0, 20000.0));
+        assert_eq!(sched.browser_logical_time, 10000.0);
+        assert_eq!(sched.current_position(), 5);
+        let trigger = &run_ticks(&mut sched, 1)[0];
+        assert_eq!(
+            (trigger.sample_id.as_str(), trigger.params["lpf"]),
+            ("hh", 200.0)
+        );
+
+        // seeking lands on the same events as playing up to there, periods of the line
+        // (12 events here) are skipped rather than generated
+        let steps_per_bar = sched.clock.steps_per_bar();
+        sched.seek(100, 5);
+        let target = 100 * steps_per_bar + 5;
+        assert_eq!(sched.current_position() as u64, target);
+        assert_eq!(sched.current_bar(), 100);
+        let trigger = &run_ticks(&mut sched, 1)[0];
+        let names = ["bd", "sn", "hh"];
+        assert_eq!(trigger.sample_id, names[(target % 3) as usize]);
+        assert_eq!(trigger.params["lpf"], 100.0 * (target % 4 + 1) as f32);
+        assert_eq!(trigger.cycle, target / 3);
+
+        // far away positions are out of reach
+        sched.seek(u32::MAX, u32::MAX);
+        assert_eq!(sched.current_bar(), MAX_SEEK_BARS);
+
+        sched.reset();
+        assert_eq!(sched.current_position(), 0);
+        assert_eq!(run_ticks(&mut sched, 1)[0].sample_id, "bd");
+    }
+
+    #[test]
+    fn test_tempo_ramp_after_reset() {
+        let mut sched = scheduler_with("tempo 120 -> 180 over 2\ncyc >> bd");
+        let ramp_steps = 2 * sched.clock.steps_per_bar() as usize;
+        run_ticks(&mut sched, ramp_steps);
+        assert_eq!(sched.bpm(), 180.0);
+
+        // the ramp starts over
+        sched.reset();
+        assert_eq!(sched.bpm(), 120.0);
+        run_ticks(&mut sched, ramp_steps / 2);
+        assert_eq!(sched.bpm(), 150.0);
+        run_ticks(&mut sched, ramp_steps / 2);
+        assert_eq!(sched.bpm(), 180.0);
+
+        // and from where it was sought to
+        sched.seek(4, 0);
+        assert_eq!(sched.bpm(), 120.0);
+        run_ticks(&mut sched, ramp_steps);
+        assert_eq!(sched.bpm(), 180.0);
+    }
+
+    #[test]
+    fn test_tempo_ramp_before_start() {
+        let mut sched = scheduler_with("tempo 100 -> 200 over 1\ncyc >> bd");
+        sched.start(0.0, 0.0);
+        assert_eq!(sched.bpm(), 100.0);
+        let bar = sched.clock.steps_per_bar() as usize;
+        run_ticks(&mut sched, bar / 2);
+        assert_eq!(sched.bpm(), 150.0);
+        run_ticks(&mut sched, bar / 2);
+        assert_eq!(sched.bpm(), 200.0);
+    }
+
+    #[test]
+    fn test_clock_settings() {
+        let mut sched = Scheduler::new();
+        assert!(sched.set_bpm(90.0));
+        for bpm in [0.0, -120.0, f64::NAN, f64::INFINITY] {
+            assert!(!sched.set_bpm(bpm));
+            assert!(!sched.set_tempo(bpm));
+        }
+        assert_eq!(sched.bpm(), 90.0);
+
+        assert!(sched.set_meter(3, 4));
+        assert!(!sched.set_meter(0, 4));
+        assert!(!sched.set_meter(4, 0));
+        assert_eq!(sched.clock.steps_per_bar(), 12);
+
+        assert!(sched.set_tempo(250.0));
+        assert_eq!(sched.bpm(), 80.0);
+        assert!(sched.tick_duration().is_finite());
+    }
+
+    #[test]
+    fn test_preview_matches_playback() {
+        type Played = (String, Vec<(String, f32)>, f64, usize, u64);
+        let summary = |trigger: &TriggerData| -> Played {
+            let mut params: Vec<(String, f32)> = trigger
+                .params
+                .iter()
+                .map(|(name, val)| (name.clone(), *val))
+                .collect();
+            params.sort_by(|a, b| a.0.cmp(&b.0));
+            let time = (trigger.timestamp * 1e9).round() / 1e9;
+            (
+                trigger.sample_id.clone(),
+                params,
+                time,
+                trigger.step,
+                trigger.cycle,
+            )
+        };
+
+        let mut sched = scheduler_with(
+            "cyc >> bd sn @lpf: cyc >> 100 200 300\n/2 cyc >> hh cp @rev: ramp >> 0 1 2",
+        );
+        run_ticks(&mut sched, 3);
+
+        let previews: Vec<Vec<Played>> = (0..2)
+            .map(|line| {
+                sched
+                    .preview_triggers(line, 5)
+                    .iter()
+                    .map(summary)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(previews[1].len(), 5);
+        // previewing doesn't advance anything
+        let again: Vec<Played> = sched.preview_triggers(0, 5).iter().map(summary).collect();
+        assert_eq!(again, previews[0]);
+
+        let played = run_ticks(&mut sched, 10);
+        for (line, preview) in previews.iter().enumerate() {
+            let played: Vec<Played> = played
+                .iter()
+                .filter(|trigger| trigger.track == line)
+                .take(5)
+                .map(summary)
+                .collect();
+            assert_eq!(&played, preview);
+        }
+    }
+
+    #[test]
+    fn test_freeze() {
+        let played = |triggers: Vec<TriggerData>| -> Vec<(String, Vec<(String, f32)>)> {
+            triggers
+                .into_iter()
+                .map(|trigger| {
+                    let mut params: Vec<(String, f32)> = trigger.params.into_iter().collect();
+                    params.sort_by(|a, b| a.0.cmp(&b.0));
+                    (trigger.sample_id, params)
+                })
+                .collect()
+        };
+
+        let code = "rnd >> bd sn hh ~ @lpf: rnd >> 100 200 300";
+        let mut sched = scheduler_with(code);
+        let recorded = played(run_ticks(&mut sched, 8));
+
+        // nothing to freeze yet
+        assert_eq!(sched.evaluate(Some(format!("{}\nfreeze 1 8", code))), None);
+
+        let edited = sched
+            .evaluate(Some(format!("{}\nfreeze 0 8\n", code)))
+            .unwrap();
+        let lines: Vec<&str> = edited.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], code);
+        assert!(lines[1].starts_with("cyc >> "));
+        // defaults aren't written out
+        assert!(!lines[1].contains("pos="));
+
+        // the frozen line plays what was played before
+        let mut frozen = scheduler_with(lines[1]);
+        assert_eq!(played(run_ticks(&mut frozen, 8)), recorded);
     }
 
-    /// Set tick duration.
-    pub fn set_tempo(&mut self, tempo: f64) {
-        self.tempo = tempo;
+    #[test]
+    fn test_catch_up() {
+        // plays the first tick, then the next call comes three ticks late
+        let late_call = |policy: &str| -> (Scheduler, f64) {
+            let mut sched = scheduler_with("cyc >> bd sn hh cp");
+            assert!(sched.set_catch_up(policy.to_string()));
+            sched.start(0.0, 0.0);
+            assert_eq!(sched.generate_triggers()[0].sample_id, "bd");
+            let tick_ms = sched.clock.tick_ms();
+            let next = sched.compensate_time(4.0 * tick_ms + 1.0);
+            (sched, next)
+        };
+        let played = |sched: &mut Scheduler| -> (String, f64) {
+            let trigger = sched.generate_triggers().remove(0);
+            (trigger.sample_id, trigger.timestamp - sched.lookahead)
+        };
+
+        // the missed ticks are dropped, playing on at the position it should be at
+        let (mut sched, next) = late_call("skip");
+        let tick_secs = sched.clock.tick_ms() / 1000.0;
+        assert_eq!(next, -1.0);
+        assert_eq!(sched.dropped_ticks(), 3);
+        assert_eq!(sched.current_position(), 4);
+        let (name, time) = played(&mut sched);
+        assert_eq!(name, "bd");
+        assert!((time - 4.0 * tick_secs).abs() < 1e-9);
+
+        // the missed ticks are played within one tick, right away
+        let (mut sched, next) = late_call("compress");
+        assert_eq!(next, 0.0);
+        assert_eq!(sched.dropped_ticks(), 0);
+        let mut names = Vec::new();
+        let mut times = Vec::new();
+        let mut nexts = Vec::new();
+        for _ in 0..4 {
+            let (name, time) = played(&mut sched);
+            names.push(name);
+            times.push(time);
+            nexts.push(sched.compensate_time(4.0 * sched.clock.tick_ms() + 1.0));
+        }
+        // back to the regular interval after that
+        assert_eq!(nexts[..3], [0.0, 0.0, 0.0]);
+        assert!(nexts[3] > 0.0);
+        assert_eq!(names, ["sn", "hh", "cp", "bd"]);
+        assert!((times[0] - (4.0 * tick_secs + 0.001)).abs() < 1e-9);
+        for pair in times.windows(2) {
+            assert!((pair[1] - pair[0] - tick_secs / 4.0).abs() < 1e-9);
+        }
+        assert_eq!(sched.current_position(), 5);
+
+        // the missed ticks are dropped, continuing where it left off
+        let (mut sched, next) = late_call("resync");
+        assert_eq!(next, 0.0);
+        assert_eq!(sched.dropped_ticks(), 3);
+        assert_eq!(sched.current_position(), 1);
+        let (name, time) = played(&mut sched);
+        assert_eq!(name, "sn");
+        assert!((time - (4.0 * tick_secs + 0.001)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_adaptive_lookahead() {
+        // calls coming late by the given jitter, though never by a whole tick
+        let jittery_ticks = |sched: &mut Scheduler, jitter_ms: f64, ticks| {
+            for _ in 0..ticks {
+                sched.generate_triggers();
+                let now = sched.browser_logical_time + jitter_ms;
+                sched.compensate_time(now);
+            }
+        };
+
+        let mut sched = scheduler_with("cyc >> bd");
+        sched.set_adaptive_lookahead(true);
+        sched.start(0.0, 0.0);
+        assert_eq!(sched.lookahead(), 100.0);
+
+        // widens right away
+        jittery_ticks(&mut sched, 60.0, 1);
+        let widened = sched.lookahead();
+        assert!(widened > 100.0);
+
+        // and shrinks gradually once the timing is stable
+        jittery_ticks(&mut sched, 0.0, 20);
+        assert!(sched.lookahead() < widened);
+        assert!(sched.lookahead() > widened - 21.0);
+        jittery_ticks(&mut sched, 0.0, 2000);
+        assert_eq!(sched.lookahead(), MIN_LOOKAHEAD_MS);
+        assert_eq!(sched.dropped_ticks(), 0);
+
+        // but never beyond the limit
+        sched.set_bpm(30.0);
+        let tick_ms = sched.clock.tick_ms();
+        jittery_ticks(&mut sched, 0.8 * tick_ms, 1);
+        assert_eq!(sched.lookahead(), MAX_LOOKAHEAD_MS);
+
+        // a fixed lookahead ends the adaptive mode
+        sched.set_lookahead(80.0);
+        jittery_ticks(&mut sched, 300.0, 1);
+        assert_eq!(sched.lookahead(), 80.0);
+    }
+
+    #[test]
+    fn test_nudge_within_lookahead() {
+        let offsets = |lookahead_ms: f64| -> Vec<f64> {
+            let mut sched = scheduler_with("cyc >> bd;nudge=1000 sn;nudge=-1000 hh;nudge=20");
+            sched.set_lookahead(lookahead_ms);
+            sched.start(0.0, 0.0);
+            (0..3)
+                .map(|tick| {
+                    let trigger = sched.generate_triggers().remove(0);
+                    let offset = trigger.timestamp - sched.audio_logical_time - sched.lookahead;
+                    let tick_ms = sched.clock.tick_ms();
+                    sched.advance_tick(tick_ms);
+                    assert_eq!(sched.clock.total_steps(), tick + 1);
+                    (offset * 1000.0).round()
+                })
+                .collect()
+        };
+
+        // nudges can't go further than the (current) lookahead
+        assert_eq!(offsets(50.0), [50.0, -50.0, 20.0]);
+        assert_eq!(offsets(200.0), [200.0, -200.0, 20.0]);
+    }
+
+    #[test]
+    fn test_invalid_lines() {
+        let code = "foo\ncyc >> bd\ncyc >> sn";
+        let mut sched = scheduler_with(code);
+        assert_eq!(sched.event_sequences.len(), 2);
+        let triggers = run_ticks(&mut sched, 1);
+        let played: Vec<(&str, usize, usize)> = triggers
+            .iter()
+            .map(|trigger| (trigger.sample_id.as_str(), trigger.track, trigger.line))
+            .collect();
+        assert_eq!(played, [("bd", 0, 1), ("sn", 1, 2)]);
+
+        // an invalid line between existing ones
+        sched.evaluate(Some("cyc >> bd\nfoo\ncyc >> sn\ncyc >> hh".to_string()));
+        assert_eq!(sched.event_sequences.len(), 3);
+        sched.evaluate(Some(code.to_string()));
+        assert_eq!(sched.event_sequences.len(), 2);
+
+        // as are lines running too fast
+        sched.evaluate(Some("*1000000000 cyc >> bd\n/2 cyc >> sn".to_string()));
+        assert_eq!(sched.event_sequences.len(), 1);
+        assert_eq!(sched.event_sequences[0].rate, (1, 2));
+
+        // and generators with the wrong number of values or no steps
+        sched.evaluate(Some(
+            "cyc >> bd @lpf: ramp >> 100 200\ncyc >> sn @lpf: bounce >> 1 2 0\ncyc >> hh"
+                .to_string(),
+        ));
+        assert_eq!(sched.event_sequences.len(), 1);
+    }
+
+    #[test]
+    fn test_learn_options() {
+        let mut sched = Scheduler::new();
+        sched.evaluate(Some(
+            "learn(order=2, history=10) >> bd sn bd hh @lpf: learn >> 100 200 100".to_string(),
+        ));
+        assert!(sched.automaton_size(0, None).unwrap() > 0);
+        assert!(sched.automaton_size(0, Some("lpf".to_string())).unwrap() > 0);
+        assert_eq!(sched.automaton_size(0, Some("rate".to_string())), None);
+
+        // invalid options fall back to cycling through the items
+        for options in ["order=0", "order=3, history=2", "bound=2"] {
+            sched.evaluate(Some(format!("learn({}) >> bd sn", options)));
+            assert_eq!(sched.automaton_size(0, None), None);
+        }
     }
 }
@@ -0,0 +1,74 @@
+// LOOKAHEAD
+// Adapting the lookahead to the measured timing jitter of the scheduler callbacks.
+
+/// bounds of the adaptive lookahead, in milliseconds
+pub const MIN_LOOKAHEAD_MS: f64 = 30.0;
+pub const MAX_LOOKAHEAD_MS: f64 = 500.0;
+
+/// the lookahead covers the jitter times this, plus the margin
+const JITTER_FACTOR: f64 = 2.0;
+const MARGIN_MS: f64 = 20.0;
+
+/// how fast the jitter estimate falls back after a late callback, per tick
+const JITTER_DECAY: f64 = 0.995;
+
+/// shrinking moves the events closer together, so it happens in small steps
+const MAX_SHRINK_MS: f64 = 1.0;
+
+/// Jitter estimate, following late callbacks right away and recovering slowly.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JitterTracker {
+    jitter_ms: f64,
+}
+
+impl JitterTracker {
+    /// Register how late (in milliseconds) a callback came.
+    pub fn measure(&mut self, lateness_ms: f64) {
+        self.jitter_ms = (self.jitter_ms * JITTER_DECAY).max(lateness_ms.max(0.0));
+    }
+
+    pub fn jitter_ms(&self) -> f64 {
+        self.jitter_ms
+    }
+
+    /// The lookahead to use next, given the current one. Widening happens right away,
+    /// as late events are worse than a bit more latency, shrinking only gradually.
+    pub fn adapt(&self, lookahead_ms: f64) -> f64 {
+        let target =
+            (self.jitter_ms * JITTER_FACTOR + MARGIN_MS).clamp(MIN_LOOKAHEAD_MS, MAX_LOOKAHEAD_MS);
+        if target > lookahead_ms {
+            target
+        } else {
+            (lookahead_ms - MAX_SHRINK_MS).max(target)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_lookahead() {
+        let mut tracker = JitterTracker::default();
+
+        // stable timing shrinks the lookahead step by step, down to the minimum
+        tracker.measure(1.0);
+        assert_eq!(tracker.adapt(100.0), 99.0);
+        assert_eq!(tracker.adapt(30.5), MIN_LOOKAHEAD_MS);
+
+        // a late callback widens it right away
+        tracker.measure(60.0);
+        assert_eq!(tracker.adapt(50.0), 140.0);
+
+        // and the estimate recovers slowly
+        for _ in 0..100 {
+            tracker.measure(2.0);
+        }
+        assert!(tracker.jitter_ms() < 60.0 && tracker.jitter_ms() > 30.0);
+
+        // there's a limit, though
+        tracker.measure(10000.0);
+        assert_eq!(tracker.adapt(100.0), MAX_LOOKAHEAD_MS);
+    }
+}
@@ -1,26 +1,38 @@
 use nom::{
     branch::alt,
-    bytes::complete::tag,
+    bytes::complete::{tag, take_while1},
     character::complete::alphanumeric1,
     character::complete::char,
-    multi::{many0, many1, separated_list},
+    character::complete::digit1,
+    combinator::{all_consuming, map, map_res, not, opt, verify},
+    error::ErrorKind,
+    multi::{many0, many1, separated_list, separated_nonempty_list},
     number::complete::float,
-    sequence::{delimited, pair, preceded, separated_pair},
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
     IResult,
 };
 
+use crate::clock::Quantize;
+use crate::pitch::{chord_voicing, parse_chord_symbol, parse_pitch_class};
+use crate::seqgen::LSYSTEM_MAX_DEPTH;
+
 // EVENTS
 // An event is something like "sine;freq=100;dur=100" (an event type followed by a list of parameters)
 // or just the event type.
 
 // param names can be fixed for now ...
 pub fn param_name(input: &str) -> IResult<&str, &str> {
+    alt((synth_param_name, voicing_param_name, timing_param_name))(input)
+}
+
+pub fn synth_param_name(input: &str) -> IResult<&str, &str> {
     alt((
         tag("atk"),
         tag("dec"),
         tag("del"),
         tag("dur"),
         tag("freq"),
+        tag("note"),
         tag("lvl"),
         tag("lpf"),
         tag("lpq"),
@@ -35,8 +47,36 @@ pub fn param_name(input: &str) -> IResult<&str, &str> {
     ))(input)
 }
 
+// these are resolved by the scheduler, not passed on to the synth
+pub fn voicing_param_name(input: &str) -> IResult<&str, &str> {
+    alt((
+        tag("chord"),
+        tag("inv"),
+        tag("spread"),
+        tag("oct"),
+        tag("degree"),
+    ))(input)
+}
+
+// these only affect the timing of the events
+// (nudge is in milliseconds, nudgestep in fractions of a step, bpm sets the tempo)
+pub fn timing_param_name(input: &str) -> IResult<&str, &str> {
+    alt((tag("swing"), tag("nudgestep"), tag("nudge"), tag("bpm")))(input)
+}
+
+// Cm7, F#maj7 or Bb, encoded as number
+pub fn chord_symbol(input: &str) -> IResult<&str, f32> {
+    match parse_chord_symbol(input) {
+        Some((code, len)) => Ok((&input[len..], code)),
+        None => Err(nom::Err::Error((input, ErrorKind::Tag))),
+    }
+}
+
 pub fn param(input: &str) -> IResult<&str, (&str, f32)> {
-    separated_pair(param_name, char('='), float)(input)
+    alt((
+        separated_pair(tag("chord"), char('='), chord_symbol),
+        separated_pair(param_name, char('='), float),
+    ))(input)
 }
 
 pub fn param_list(input: &str) -> IResult<&str, Vec<(&str, f32)>> {
@@ -59,12 +99,14 @@ pub fn event_without_param(input: &str) -> IResult<&str, (&str, Vec<(&str, f32)>
     Ok((res.0, (res.1, Vec::new())))
 }
 
+pub type Event<'a> = (&'a str, Vec<(&'a str, f32)>);
+
 // both of the former
-pub fn event(input: &str) -> IResult<&str, (&str, Vec<(&str, f32)>)> {
+pub fn event(input: &str) -> IResult<&str, Event<'_>> {
     alt((event_with_param, event_without_param))(input)
 }
 
-pub fn event_pattern(input: &str) -> IResult<&str, Vec<(&str, Vec<(&str, f32)>)>> {
+pub fn event_pattern(input: &str) -> IResult<&str, Vec<Event<'_>>> {
     separated_list(many1(char(' ')), event)(input)
 }
 
@@ -79,9 +121,164 @@ pub fn variable_definiton(
     )(input)
 }
 
+// KEY
+// d, F#, Bb
+pub fn pitch_class(input: &str) -> IResult<&str, i32> {
+    match parse_pitch_class(input) {
+        Some((pc, len)) => Ok((&input[len..], pc)),
+        None => Err(nom::Err::Error((input, ErrorKind::Tag))),
+    }
+}
+
+// key d dorian
+pub fn key_definition(input: &str) -> IResult<&str, (i32, &str)> {
+    preceded(
+        pair(tag("key"), many1(char(' '))),
+        separated_pair(pitch_class, many1(char(' ')), alphanumeric1),
+    )(input)
+}
+
+// names of tunings and grooves, like "bohlen-pierce"
+pub fn identifier(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| c.is_alphanumeric() || c == '-' || c == '_')(input)
+}
+
+// TUNING
+// tuning pythagorean, selecting a loaded scala tuning
+pub fn tuning_definition(input: &str) -> IResult<&str, &str> {
+    preceded(pair(tag("tuning"), many1(char(' '))), identifier)(input)
+}
+
+// GROOVE
+// 0.1:0.8, timing offset (in steps) and level of a groove step
+pub fn groove_step(input: &str) -> IResult<&str, (f32, f32)> {
+    separated_pair(float, char(':'), float)(input)
+}
+
+// groove lazy = 0:1, 0.1:0.8, a custom groove
+pub fn groove_definition(input: &str) -> IResult<&str, (&str, Vec<(f32, f32)>)> {
+    preceded(
+        pair(tag("groove"), many1(char(' '))),
+        separated_pair(
+            identifier,
+            delimited(many0(char(' ')), char('='), many0(char(' '))),
+            separated_nonempty_list(list_separator, groove_step),
+        ),
+    )(input)
+}
+
+// groove mpc16, selecting a groove for all lines
+pub fn groove_selection(input: &str) -> IResult<&str, &str> {
+    preceded(pair(tag("groove"), many1(char(' '))), identifier)(input)
+}
+
+// swing 58, in percent
+pub fn swing_setting(input: &str) -> IResult<&str, f32> {
+    preceded(pair(tag("swing"), many1(char(' '))), float)(input)
+}
+
+// @groove: mpc16, the groove of a single line
+pub fn line_groove(input: &str) -> IResult<&str, (&str, ParamExpr<'_>)> {
+    let (rest, name) = preceded(
+        pair(
+            tag("@groove"),
+            delimited(many0(char(' ')), char(':'), many0(char(' '))),
+        ),
+        identifier,
+    )(input)?;
+    Ok((rest, ("groove", ParamExpr::Groove(name))))
+}
+
+// TEMPO
+// 120bpm, the unit is optional
+pub fn bpm_value(input: &str) -> IResult<&str, f32> {
+    terminated(
+        verify(float, |bpm: &f32| bpm.is_finite() && *bpm > 0.0),
+        opt(tag("bpm")),
+    )(input)
+}
+
+// tempo 120bpm, or tempo 120bpm -> 140bpm over 8 (bars)
+pub fn tempo_setting(input: &str) -> IResult<&str, (f32, Option<(f32, u32)>)> {
+    preceded(
+        pair(tag("tempo"), many1(char(' '))),
+        pair(
+            bpm_value,
+            opt(preceded(
+                delimited(many0(char(' ')), tag("->"), many0(char(' '))),
+                separated_pair(
+                    bpm_value,
+                    delimited(many1(char(' ')), tag("over"), many1(char(' '))),
+                    map_res(digit1, |s: &str| s.parse::<u32>()),
+                ),
+            )),
+        ),
+    )(input)
+}
+
+// FREEZE
+// freeze 0 16, the last 16 steps of the first pattern line
+pub fn freeze_command(input: &str) -> IResult<&str, (usize, usize)> {
+    preceded(
+        pair(tag("freeze"), many1(char(' '))),
+        separated_pair(
+            map_res(digit1, |s: &str| s.parse::<usize>()),
+            many1(char(' ')),
+            map_res(digit1, |s: &str| s.parse::<usize>()),
+        ),
+    )(input)
+}
+
+// LINE RATE
+// /2 (every other tick), *3 (three events per tick) or 3:4 (three events in four ticks),
+// as (events, ticks), both up to MAX_LINE_RATE
+pub const MAX_LINE_RATE: u64 = 16;
+
+pub fn line_rate(input: &str) -> IResult<&str, (u64, u64)> {
+    let number = |input| map_res(digit1, |s: &str| s.parse::<u64>())(input);
+    terminated(
+        verify(
+            alt((
+                map(preceded(char('/'), number), |ticks| (1, ticks)),
+                map(preceded(char('*'), number), |events| (events, 1)),
+                separated_pair(number, char(':'), number),
+            )),
+            |(events, ticks)| {
+                (1..=MAX_LINE_RATE).contains(events) && (1..=MAX_LINE_RATE).contains(ticks)
+            },
+        ),
+        many1(char(' ')),
+    )(input)
+}
+
+// QUANTIZE
+// quantize off, quantize bar, quantize 4 (bars) or quantize line 2 (when its cycle wraps)
+pub fn quantize_setting(input: &str) -> IResult<&str, Quantize> {
+    preceded(
+        pair(tag("quantize"), many1(char(' '))),
+        alt((
+            map(tag("off"), |_| Quantize::Off),
+            map(tag("bar"), |_| Quantize::Bars(1)),
+            map(
+                preceded(
+                    pair(tag("line"), many1(char(' '))),
+                    map_res(digit1, |s: &str| s.parse::<usize>()),
+                ),
+                Quantize::Line,
+            ),
+            map(
+                verify(map_res(digit1, |s: &str| s.parse::<u32>()), |bars| {
+                    *bars > 0
+                }),
+                Quantize::Bars,
+            ),
+        )),
+    )(input)
+}
+
 // SEQ GENS
 pub fn pattern_func_name(input: &str) -> IResult<&str, &str> {
-    alt((tag("rnd"), tag("cyc"), tag("learn")))(input)
+    alt((tag("rnd"), tag("cyc"), tag("shuffle"), tag("norepeat")))(input)
 }
 
 pub fn param_func_name(input: &str) -> IResult<&str, &str> {
@@ -92,50 +289,524 @@ pub fn func_name(input: &str) -> IResult<&str, &str> {
     alt((param_func_name, pattern_func_name))(input)
 }
 
-pub fn pattern_func(input: &str) -> IResult<&str, (&str, Vec<(&str, Vec<(&str, f32)>)>)> {
+pub fn func_separator(input: &str) -> IResult<&str, &str> {
+    delimited(many0(char(' ')), tag(">>"), many0(char(' ')))(input)
+}
+
+// GENERATOR DEFINITIONS
+// The generator definitions are shared between the event pattern and the
+// parameter sequences, only the item type differs.
+#[derive(Debug, PartialEq)]
+pub enum GenDef<'a, T> {
+    /// a generator over a list of items, i.e. "cyc >> bd sn" or "rnd >> 1 2 3"
+    Items(&'a str, Vec<T>),
+    /// a markov chain with explicit (source, destination, probability) transitions
+    Markov(Vec<(T, T, f32)>),
+    /// a variable-order markov chain inferred from (history, symbol, probability) rules
+    Infer(Vec<(Vec<T>, T, f32)>),
+    /// a cellular automaton with its options, switching between the (on, off) items
+    Automaton(Vec<(&'a str, u64)>, Vec<T>),
+    /// an l-system with axiom, rewrite rules and depth, expanded and cycled
+    LSystem(Vec<T>, Vec<(T, Vec<T>)>, usize),
+    /// a markov chain learned from the items, with optional learning parameters
+    Learn(Vec<(&'a str, f32)>, Vec<T>),
+}
+
+// PARAMETER EXPRESSIONS
+// Parameter generators can be combined arithmetically and piped through modifiers,
+// i.e. "cyc >> 100 200 * ramp >> 1 2 8 | quantize(50)".
+#[derive(Debug, PartialEq)]
+pub enum ParamExpr<'a> {
+    Gen(GenDef<'a, f32>),
+    Mul(Box<ParamExpr<'a>>, Box<ParamExpr<'a>>),
+    Add(Box<ParamExpr<'a>>, Box<ParamExpr<'a>>),
+    /// modifier name and arguments, applied to the inner expression
+    Modified(&'a str, Vec<f32>, Box<ParamExpr<'a>>),
+    /// breakpoint envelope with (value, step) points, looping or one-shot
+    Envelope(Vec<(f32, usize)>, bool),
+    /// arpeggio with mode, octave range and note numbers
+    Arp(&'a str, usize, Vec<f32>),
+    /// not a generator, but the groove name of the line
+    Groove(&'a str),
+}
+
+pub type PatternLine<'a> = (GenDef<'a, Event<'a>>, Vec<(&'a str, ParamExpr<'a>)>);
+
+pub fn list_separator(input: &str) -> IResult<&str, char> {
+    delimited(many0(char(' ')), char(','), many0(char(' ')))(input)
+}
+
+// bd -> sn 0.7
+pub fn markov_transition<'a, T, F>(item: F) -> impl Fn(&'a str) -> IResult<&'a str, (T, T, f32)>
+where
+    F: Fn(&'a str) -> IResult<&'a str, T> + Copy,
+{
+    move |input: &'a str| {
+        let (rest, src) = item(input)?;
+        let (rest, _) = delimited(many0(char(' ')), tag("->"), many0(char(' ')))(rest)?;
+        let (rest, dst) = item(rest)?;
+        let (rest, prob) = preceded(many1(char(' ')), float)(rest)?;
+        Ok((rest, (src, dst, prob)))
+    }
+}
+
+// markov >> bd -> sn 0.7, bd -> hh 0.3, sn -> bd 1.0
+pub fn markov_func<'a, T, F>(item: F) -> impl Fn(&'a str) -> IResult<&'a str, GenDef<'a, T>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, T> + Copy,
+{
+    move |input: &'a str| {
+        let (rest, transitions) = preceded(
+            pair(tag("markov"), func_separator),
+            separated_list(list_separator, markov_transition(item)),
+        )(input)?;
+        Ok((rest, GenDef::Markov(transitions)))
+    }
+}
+
+// bd sn -> hh 0.5
+pub fn infer_rule<'a, T, F>(item: F) -> impl Fn(&'a str) -> IResult<&'a str, (Vec<T>, T, f32)>
+where
+    F: Fn(&'a str) -> IResult<&'a str, T> + Copy,
+{
+    move |input: &'a str| {
+        let (rest, source) = separated_nonempty_list(many1(char(' ')), item)(input)?;
+        let (rest, _) = delimited(many0(char(' ')), tag("->"), many0(char(' ')))(rest)?;
+        let (rest, symbol) = item(rest)?;
+        let (rest, prob) = preceded(many1(char(' ')), float)(rest)?;
+        Ok((rest, (source, symbol, prob)))
+    }
+}
+
+// infer >> bd sn -> hh 0.5, bd sn -> bd 0.5, sn hh -> bd 1.0
+pub fn infer_func<'a, T, F>(item: F) -> impl Fn(&'a str) -> IResult<&'a str, GenDef<'a, T>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, T> + Copy,
+{
+    move |input: &'a str| {
+        let (rest, rules) = preceded(
+            pair(tag("infer"), func_separator),
+            separated_list(list_separator, infer_rule(item)),
+        )(input)?;
+        Ok((rest, GenDef::Infer(rules)))
+    }
+}
+
+pub fn learn_option_name(input: &str) -> IResult<&str, &str> {
+    alt((tag("order"), tag("bound"), tag("history")))(input)
+}
+
+pub fn learn_option(input: &str) -> IResult<&str, (&str, f32)> {
+    separated_pair(
+        learn_option_name,
+        delimited(many0(char(' ')), char('='), many0(char(' '))),
+        float,
+    )(input)
+}
+
+// (order=2, bound=0.05)
+pub fn learn_options(input: &str) -> IResult<&str, Vec<(&str, f32)>> {
+    delimited(
+        pair(char('('), many0(char(' '))),
+        separated_list(list_separator, learn_option),
+        pair(many0(char(' ')), char(')')),
+    )(input)
+}
+
+// learn >> bd sn or learn(order=2, bound=0.05) >> bd sn
+pub fn learn_func<'a, T, F>(items: F) -> impl Fn(&'a str) -> IResult<&'a str, GenDef<'a, T>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, Vec<T>> + Copy,
+{
+    move |input: &'a str| {
+        let (rest, (options, learn_items)) = separated_pair(
+            preceded(tag("learn"), opt(learn_options)),
+            func_separator,
+            items,
+        )(input)?;
+        Ok((
+            rest,
+            GenDef::Learn(options.unwrap_or_default(), learn_items),
+        ))
+    }
+}
+
+pub fn ca_option_name(input: &str) -> IResult<&str, &str> {
+    alt((tag("rule"), tag("width"), tag("seed")))(input)
+}
+
+pub fn ca_option(input: &str) -> IResult<&str, (&str, u64)> {
     separated_pair(
-        func_name,
-        delimited(many0(char(' ')), tag(">>"), many0(char(' '))),
-        event_pattern,
+        ca_option_name,
+        char('='),
+        map_res(digit1, |digits: &str| digits.parse::<u64>()),
     )(input)
 }
 
+// an event that isn't the start of an option like "rule=30"
+pub fn ca_event(input: &str) -> IResult<&str, Event<'_>> {
+    terminated(event, not(char('=')))(input)
+}
+
+// ca >> bd rule=30 width=16 seed=5 or ca >> bd hh rule=90
+pub fn ca_func<'a, T, F>(items: F) -> impl Fn(&'a str) -> IResult<&'a str, GenDef<'a, T>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, T> + Copy,
+{
+    move |input: &'a str| {
+        let (rest, ca_items) = preceded(
+            pair(tag("ca"), func_separator),
+            separated_nonempty_list(many1(char(' ')), items),
+        )(input)?;
+        let (rest, options) = many0(preceded(many1(char(' ')), ca_option))(rest)?;
+        Ok((rest, GenDef::Automaton(options, ca_items)))
+    }
+}
+
+// bd or bd_sn_hh
+pub fn lsys_word<'a, T, F>(symbol: F) -> impl Fn(&'a str) -> IResult<&'a str, Vec<T>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, T> + Copy,
+{
+    move |input: &'a str| separated_nonempty_list(char('_'), symbol)(input)
+}
+
+// lsys >> axiom=bd rules=bd:bd_sn,sn:hh_bd depth=4
+pub fn lsys_func<'a, T, F>(symbol: F) -> impl Fn(&'a str) -> IResult<&'a str, GenDef<'a, T>>
+where
+    F: Fn(&'a str) -> IResult<&'a str, T> + Copy,
+{
+    move |input: &'a str| {
+        let (rest, axiom) = preceded(
+            pair(tag("lsys"), func_separator),
+            preceded(tag("axiom="), lsys_word(symbol)),
+        )(input)?;
+        let (rest, rules) = preceded(
+            pair(many1(char(' ')), tag("rules=")),
+            separated_list(
+                char(','),
+                separated_pair(symbol, char(':'), lsys_word(symbol)),
+            ),
+        )(rest)?;
+        let (rest, depth) = opt(preceded(
+            pair(many1(char(' ')), tag("depth=")),
+            verify(
+                map_res(digit1, |digits: &str| digits.parse::<usize>()),
+                |depth| *depth <= LSYSTEM_MAX_DEPTH,
+            ),
+        ))(rest)?;
+        Ok((rest, GenDef::LSystem(axiom, rules, depth.unwrap_or(3))))
+    }
+}
+
+// l-system symbols are plain event names, "_" separates them
+pub fn lsys_event(input: &str) -> IResult<&str, Event<'_>> {
+    let (rest, name) = alt((tag("~"), alphanumeric1))(input)?;
+    Ok((rest, (name, Vec::new())))
+}
+
+pub fn event_items_func(input: &str) -> IResult<&str, GenDef<'_, Event<'_>>> {
+    let (rest, (name, events)) = separated_pair(func_name, func_separator, event_pattern)(input)?;
+    Ok((rest, GenDef::Items(name, events)))
+}
+
+pub fn pattern_func(input: &str) -> IResult<&str, GenDef<'_, Event<'_>>> {
+    alt((
+        markov_func(event),
+        infer_func(event),
+        ca_func(ca_event),
+        lsys_func(lsys_event),
+        learn_func(event_pattern),
+        event_items_func,
+    ))(input)
+}
+
+// lfo sine, the shape takes the place of the function name
+pub fn lfo_shape_name(input: &str) -> IResult<&str, &str> {
+    alt((
+        tag("sine"),
+        tag("tri"),
+        tag("saw"),
+        tag("square"),
+        tag("sah"),
+    ))(input)
+}
+
+pub fn lfo_func(input: &str) -> IResult<&str, &str> {
+    preceded(pair(tag("lfo"), many1(char(' '))), lfo_shape_name)(input)
+}
+
+// 0.25 or 0.25hz
+pub fn param_value(input: &str) -> IResult<&str, f32> {
+    terminated(float, opt(tag("hz")))(input)
+}
+
+// Cm7, or the number it's encoded as
+pub fn chord_value(input: &str) -> IResult<&str, f32> {
+    alt((chord_symbol, float))(input)
+}
+
 pub fn param_func_header(input: &str) -> IResult<&str, &str> {
     preceded(tag("@"), param_name)(input)
 }
 
-pub fn param_func(input: &str) -> IResult<&str, (&str, &str)> {
+pub fn param_values(input: &str) -> IResult<&str, Vec<f32>> {
+    separated_list(many1(char(' ')), param_value)(input)
+}
+
+// ramp, bounce and the lfos take exactly three values,
+// and ramp and bounce need at least one step
+fn valid_items(name: &str, values: &[f32]) -> bool {
+    match name {
+        "ramp" | "bounce" => values.len() == 3 && values[2] > 0.0,
+        "sine" | "tri" | "saw" | "square" | "sah" => values.len() == 3,
+        _ => true,
+    }
+}
+
+pub fn param_items_func(input: &str) -> IResult<&str, GenDef<'_, f32>> {
+    let (rest, (name, values)) = verify(
+        separated_pair(alt((lfo_func, func_name)), func_separator, param_values),
+        |(name, values): &(&str, Vec<f32>)| valid_items(name, values),
+    )(input)?;
+    Ok((rest, GenDef::Items(name, values)))
+}
+
+pub fn chord_values(input: &str) -> IResult<&str, Vec<f32>> {
+    separated_list(many1(char(' ')), chord_value)(input)
+}
+
+pub fn chord_items_func(input: &str) -> IResult<&str, GenDef<'_, f32>> {
+    let (rest, (name, values)) = verify(
+        separated_pair(func_name, func_separator, chord_values),
+        |(name, values): &(&str, Vec<f32>)| valid_items(name, values),
+    )(input)?;
+    Ok((rest, GenDef::Items(name, values)))
+}
+
+// the generators that can sequence chord symbols
+pub fn chord_gen_def(input: &str) -> IResult<&str, GenDef<'_, f32>> {
+    alt((
+        markov_func(chord_value),
+        infer_func(chord_value),
+        ca_func(chord_value),
+        lsys_func(chord_value),
+        learn_func(chord_values),
+        chord_items_func,
+    ))(input)
+}
+
+pub fn param_gen_def(input: &str) -> IResult<&str, GenDef<'_, f32>> {
+    alt((
+        markov_func(param_value),
+        infer_func(param_value),
+        ca_func(param_value),
+        lsys_func(float),
+        learn_func(param_values),
+        param_items_func,
+    ))(input)
+}
+
+// 200:0
+pub fn breakpoint(input: &str) -> IResult<&str, (f32, usize)> {
     separated_pair(
-        param_func_header,
-        delimited(many0(char(' ')), char(':'), many0(char(' '))),
-        func_name,
+        float,
+        char(':'),
+        map_res(digit1, |digits: &str| digits.parse::<usize>()),
     )(input)
 }
 
-pub fn param_func_with_values(input: &str) -> IResult<&str, ((&str, &str), Vec<f32>)> {
-    separated_pair(
-        param_func,
-        delimited(many0(char(' ')), tag(">>"), many0(char(' '))),
+// env >> 200:0 4000:8 300:16 loops, env once >> 200:0 4000:8 holds the last value
+pub fn envelope_func(input: &str) -> IResult<&str, ParamExpr<'_>> {
+    let (rest, (once, points)) = separated_pair(
+        preceded(tag("env"), opt(preceded(many1(char(' ')), tag("once")))),
+        func_separator,
+        separated_list(many1(char(' ')), breakpoint),
+    )(input)?;
+    Ok((rest, ParamExpr::Envelope(points, once.is_none())))
+}
+
+pub fn arp_mode_name(input: &str) -> IResult<&str, &str> {
+    alt((
+        tag("updown"),
+        tag("up"),
+        tag("down"),
+        tag("random"),
+        tag("converge"),
+    ))(input)
+}
+
+// the notes of a chord symbol (rooted in octave 4) or a list of note numbers
+pub fn arp_notes(input: &str) -> IResult<&str, Vec<f32>> {
+    alt((
+        map(chord_symbol, |code| chord_voicing(code, 4, 0, 0)),
         separated_list(many1(char(' ')), float),
+    ))(input)
+}
+
+// arp >> 60 64 67, arp updown 2 >> 60 64 67 (mode and octave range) or arp >> Cm7
+pub fn arp_func(input: &str) -> IResult<&str, ParamExpr<'_>> {
+    let (rest, ((mode, octaves), notes)) = separated_pair(
+        preceded(
+            tag("arp"),
+            pair(
+                opt(preceded(many1(char(' ')), arp_mode_name)),
+                opt(preceded(
+                    many1(char(' ')),
+                    map_res(digit1, |digits: &str| digits.parse::<usize>()),
+                )),
+            ),
+        ),
+        func_separator,
+        arp_notes,
+    )(input)?;
+    Ok((
+        rest,
+        ParamExpr::Arp(mode.unwrap_or("up"), octaves.unwrap_or(1), notes),
+    ))
+}
+
+// a single generator or a parenthesized expression
+pub fn param_operand(input: &str) -> IResult<&str, ParamExpr<'_>> {
+    alt((
+        envelope_func,
+        arp_func,
+        delimited(
+            pair(char('('), many0(char(' '))),
+            param_expr,
+            pair(many0(char(' ')), char(')')),
+        ),
+        |i| {
+            let (rest, def) = param_gen_def(i)?;
+            Ok((rest, ParamExpr::Gen(def)))
+        },
+    ))(input)
+}
+
+// left-associative chain of operands, "*" binds tighter than "+"
+pub fn param_product(input: &str) -> IResult<&str, ParamExpr<'_>> {
+    let (rest, first) = param_operand(input)?;
+    let (rest, others) = many0(preceded(
+        delimited(many0(char(' ')), char('*'), many0(char(' '))),
+        param_operand,
+    ))(rest)?;
+    let expr = others.into_iter().fold(first, |acc, operand| {
+        ParamExpr::Mul(Box::new(acc), Box::new(operand))
+    });
+    Ok((rest, expr))
+}
+
+pub fn param_sum(input: &str) -> IResult<&str, ParamExpr<'_>> {
+    let (rest, first) = param_product(input)?;
+    let (rest, others) = many0(preceded(
+        delimited(many0(char(' ')), char('+'), many0(char(' '))),
+        param_product,
+    ))(rest)?;
+    let expr = others.into_iter().fold(first, |acc, operand| {
+        ParamExpr::Add(Box::new(acc), Box::new(operand))
+    });
+    Ok((rest, expr))
+}
+
+pub fn modifier_name(input: &str) -> IResult<&str, &str> {
+    alt((tag("quantize"), tag("scale"), tag("clamp"), tag("offset")))(input)
+}
+
+// scale(100, 800)
+pub fn modifier(input: &str) -> IResult<&str, (&str, Vec<f32>)> {
+    pair(
+        modifier_name,
+        delimited(
+            pair(char('('), many0(char(' '))),
+            separated_list(list_separator, float),
+            pair(many0(char(' ')), char(')')),
+        ),
     )(input)
 }
 
-pub fn pattern_line(
-    input: &str,
-) -> IResult<
-    &str,
-    (
-        (&str, Vec<(&str, Vec<(&str, f32)>)>),
-        Vec<((&str, &str), Vec<f32>)>,
-    ),
-> {
+// lfo sine >> 0.5hz 0 1 | scale(100, 800) | quantize(50)
+pub fn param_expr(input: &str) -> IResult<&str, ParamExpr<'_>> {
+    let (rest, sum) = param_sum(input)?;
+    let (rest, modifiers) = many0(preceded(
+        delimited(many0(char(' ')), char('|'), many0(char(' '))),
+        modifier,
+    ))(rest)?;
+    let expr = modifiers.into_iter().fold(sum, |acc, (name, args)| {
+        ParamExpr::Modified(name, args, Box::new(acc))
+    });
+    Ok((rest, expr))
+}
+
+// @chord: cyc >> Cm7 F7 Bb, chord symbols are only valid here
+pub fn chord_func_with_values(input: &str) -> IResult<&str, (&str, ParamExpr<'_>)> {
+    separated_pair(
+        preceded(char('@'), tag("chord")),
+        delimited(many0(char(' ')), char(':'), many0(char(' '))),
+        map(chord_gen_def, ParamExpr::Gen),
+    )(input)
+}
+
+pub fn param_func_with_values(input: &str) -> IResult<&str, (&str, ParamExpr<'_>)> {
+    alt((
+        chord_func_with_values,
+        separated_pair(
+            param_func_header,
+            delimited(many0(char(' ')), char(':'), many0(char(' '))),
+            param_expr,
+        ),
+    ))(input)
+}
+
+pub fn pattern_line(input: &str) -> IResult<&str, PatternLine<'_>> {
     separated_pair(
         pattern_func,
         many0(char(' ')),
-        separated_list(many1(char(' ')), param_func_with_values),
+        separated_list(many1(char(' ')), alt((line_groove, param_func_with_values))),
     )(input)
 }
 
+// STATEMENTS
+// Every line of code is one of these, as a whole (so half-written lines are errors
+// rather than taken for something shorter).
+#[derive(Debug, PartialEq)]
+pub enum Statement<'a> {
+    Variable(&'a str, Event<'a>),
+    Key(i32, &'a str),
+    Tempo(f32, Option<(f32, u32)>),
+    Quantize(Quantize),
+    Freeze(usize, usize),
+    GrooveDefinition(&'a str, Vec<(f32, f32)>),
+    GrooveSelection(&'a str),
+    Swing(f32),
+    Tuning(&'a str),
+    /// the rate as (events, ticks) and the pattern itself
+    Pattern((u64, u64), PatternLine<'a>),
+}
+
+pub fn statement(input: &str) -> IResult<&str, Statement<'_>> {
+    all_consuming(alt((
+        map(variable_definiton, |((_, name), event)| {
+            Statement::Variable(name, event)
+        }),
+        map(key_definition, |(root, scale)| Statement::Key(root, scale)),
+        map(tempo_setting, |(bpm, ramp)| Statement::Tempo(bpm, ramp)),
+        map(quantize_setting, Statement::Quantize),
+        map(freeze_command, |(line, steps)| {
+            Statement::Freeze(line, steps)
+        }),
+        // the definition has to come first, as it starts like a selection
+        map(groove_definition, |(name, steps)| {
+            Statement::GrooveDefinition(name, steps)
+        }),
+        map(groove_selection, Statement::GrooveSelection),
+        map(swing_setting, Statement::Swing),
+        map(tuning_definition, Statement::Tuning),
+        map(pair(opt(line_rate), pattern_line), |(rate, line)| {
+            Statement::Pattern(rate.unwrap_or((1, 1)), line)
+        }),
+    )))(input)
+}
+
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
@@ -162,6 +833,28 @@ mod tests {
         assert!(res.is_ok());
     }
 
+    #[test]
+    fn test_shuffle_and_norepeat() {
+        let res = pattern_line("shuffle >> bd sn hh @rate: norepeat >> 1.0 0.9 0.6");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    GenDef::Items(
+                        "shuffle",
+                        vec![("bd", vec![]), ("sn", vec![]), ("hh", vec![])]
+                    ),
+                    vec![(
+                        "rate",
+                        ParamExpr::Gen(GenDef::Items("norepeat", vec![1.0, 0.9, 0.6]))
+                    )]
+                )
+            ))
+        );
+    }
+
     #[test]
     fn test_param_func() {
         let res = param_func_with_values("@rate: rnd >> 1.0 0.9 0.6 0.4");
@@ -169,6 +862,353 @@ mod tests {
         assert!(res.is_ok());
     }
 
+    #[test]
+    fn test_lfo_param_func() {
+        let res = param_func_with_values("@pos: lfo sine >> 0.25hz 0 1");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    "pos",
+                    ParamExpr::Gen(GenDef::Items("sine", vec![0.25, 0.0, 1.0]))
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_param_func_arity() {
+        assert!(statement("cyc >> bd @pos: lfo sine >> 0.25hz 0").is_err());
+        assert!(statement("cyc >> bd @pos: lfo sine >> 0.25hz 0 1 2").is_err());
+        assert!(statement("cyc >> bd @lpf: ramp >> 100 200").is_err());
+        assert!(statement("cyc >> bd @lpf: ramp >> 100").is_err());
+        assert!(statement("cyc >> bd @lpf: ramp >> 100 200 4").is_ok());
+
+        // ramp and bounce need at least one step
+        assert!(statement("cyc >> bd @lpf: ramp >> 1 1 0").is_err());
+        assert!(statement("cyc >> bd @lpf: bounce >> 1 2 0").is_err());
+        assert!(statement("cyc >> bd @lpf: bounce >> 1 2 -3").is_err());
+        assert!(statement("cyc >> sine @chord: ramp >> Cm7 Fm7 0").is_err());
+    }
+
+    #[test]
+    fn test_markov_pattern_line() {
+        let res =
+            pattern_line("markov >> bd -> sn 0.7, bd -> hh 0.3, sn -> bd 1.0 @rate: cyc >> 1");
+        println!("Result: {:?}", res);
+        match res {
+            Ok(("", (GenDef::Markov(transitions), params))) => {
+                assert_eq!(transitions.len(), 3);
+                assert_eq!(transitions[1], (("bd", vec![]), ("hh", vec![]), 0.3));
+                assert_eq!(params.len(), 1);
+            }
+            _ => panic!("markov line not parsed"),
+        }
+    }
+
+    #[test]
+    fn test_markov_param_func() {
+        let res = param_func_with_values("@rate: markov >> 1 -> 0.5 0.7, 1->1 0.3,0.5 -> 1 1.0");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    "rate",
+                    ParamExpr::Gen(GenDef::Markov(vec![
+                        (1.0, 0.5, 0.7),
+                        (1.0, 1.0, 0.3),
+                        (0.5, 1.0, 1.0)
+                    ]))
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_infer_pattern_line() {
+        let res = pattern_line("infer >> bd sn -> hh 0.5, bd sn -> bd 0.5, sn hh -> bd 1.0");
+        println!("Result: {:?}", res);
+        match res {
+            Ok(("", (GenDef::Infer(rules), _))) => {
+                assert_eq!(rules.len(), 3);
+                assert_eq!(
+                    rules[0],
+                    (vec![("bd", vec![]), ("sn", vec![])], ("hh", vec![]), 0.5)
+                );
+            }
+            _ => panic!("infer line not parsed"),
+        }
+    }
+
+    #[test]
+    fn test_infer_param_func() {
+        let res = param_func_with_values("@rate: infer >> 1 0.5 -> 2 1.0, 0.5 2 -> 1 1.0");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    "rate",
+                    ParamExpr::Gen(GenDef::Infer(vec![
+                        (vec![1.0, 0.5], 2.0, 1.0),
+                        (vec![0.5, 2.0], 1.0, 1.0)
+                    ]))
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_ca_pattern_line() {
+        let res = pattern_line("ca >> bd rule=30 width=16 seed=5 @rate: ca >> 1 0.5 rule=90");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    GenDef::Automaton(
+                        vec![("rule", 30), ("width", 16), ("seed", 5)],
+                        vec![("bd", vec![])]
+                    ),
+                    vec![(
+                        "rate",
+                        ParamExpr::Gen(GenDef::Automaton(vec![("rule", 90)], vec![1.0, 0.5]))
+                    )]
+                )
+            ))
+        );
+
+        let res = pattern_line("ca >> bd hh");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    GenDef::Automaton(vec![], vec![("bd", vec![]), ("hh", vec![])]),
+                    vec![]
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_lsys_pattern_line() {
+        let res = pattern_line("lsys >> axiom=bd rules=bd:bd_sn,sn:hh_bd depth=4");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    GenDef::LSystem(
+                        vec![("bd", vec![])],
+                        vec![
+                            (("bd", vec![]), vec![("bd", vec![]), ("sn", vec![])]),
+                            (("sn", vec![]), vec![("hh", vec![]), ("bd", vec![])])
+                        ],
+                        4
+                    ),
+                    vec![]
+                )
+            ))
+        );
+
+        let res = param_func_with_values("@rate: lsys >> axiom=1 rules=1:1_0.5");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    "rate",
+                    ParamExpr::Gen(GenDef::LSystem(vec![1.0], vec![(1.0, vec![1.0, 0.5])], 3))
+                )
+            ))
+        );
+
+        // the depth is limited
+        assert!(statement("lsys >> axiom=bd rules=bd:sn depth=64").is_ok());
+        assert!(statement("lsys >> axiom=bd rules=bd:sn depth=4000000000").is_err());
+    }
+
+    #[test]
+    fn test_learn_with_options() {
+        let res = pattern_line("learn(order=2, bound=0.05) >> bd sn @rate: learn >> 1 2");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    GenDef::Learn(
+                        vec![("order", 2.0), ("bound", 0.05)],
+                        vec![("bd", vec![]), ("sn", vec![])]
+                    ),
+                    vec![(
+                        "rate",
+                        ParamExpr::Gen(GenDef::Learn(vec![], vec![1.0, 2.0]))
+                    )]
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_param_expr() {
+        let res = param_func_with_values("@freq: cyc >> 100 200 * ramp >> 1 2 8");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    "freq",
+                    ParamExpr::Mul(
+                        Box::new(ParamExpr::Gen(GenDef::Items("cyc", vec![100.0, 200.0]))),
+                        Box::new(ParamExpr::Gen(GenDef::Items("ramp", vec![1.0, 2.0, 8.0])))
+                    )
+                )
+            ))
+        );
+
+        let res = param_expr("cyc >> 1 + cyc >> 2 * cyc >> 3 | scale(100, 800) | quantize(50)");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                ParamExpr::Modified(
+                    "quantize",
+                    vec![50.0],
+                    Box::new(ParamExpr::Modified(
+                        "scale",
+                        vec![100.0, 800.0],
+                        Box::new(ParamExpr::Add(
+                            Box::new(ParamExpr::Gen(GenDef::Items("cyc", vec![1.0]))),
+                            Box::new(ParamExpr::Mul(
+                                Box::new(ParamExpr::Gen(GenDef::Items("cyc", vec![2.0]))),
+                                Box::new(ParamExpr::Gen(GenDef::Items("cyc", vec![3.0])))
+                            ))
+                        ))
+                    ))
+                )
+            ))
+        );
+
+        let res = pattern_line(
+            "cyc >> bd sn @lpf: (cyc >> 1 2 | offset(1)) * lfo saw >> 1hz 100 200 @rate: cyc >> 1",
+        );
+        println!("Result: {:?}", res);
+        assert!(res.is_ok());
+        let (rest, (_, params)) = res.unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(params.len(), 2);
+    }
+
+    #[test]
+    fn test_envelope_param_func() {
+        let res = param_func_with_values("@lpf: env >> 200:0 4000:8 300:16");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    "lpf",
+                    ParamExpr::Envelope(vec![(200.0, 0), (4000.0, 8), (300.0, 16)], true)
+                )
+            ))
+        );
+
+        let res = param_func_with_values("@lpf: env once >> 200:0 4000:8");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    "lpf",
+                    ParamExpr::Envelope(vec![(200.0, 0), (4000.0, 8)], false)
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_arp_param_func() {
+        let res = param_func_with_values("@freq: arp updown 2 >> 60 64 67");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                ("freq", ParamExpr::Arp("updown", 2, vec![60.0, 64.0, 67.0]))
+            ))
+        );
+
+        let res = param_func_with_values("@note: arp >> 60 63");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok(("", ("note", ParamExpr::Arp("up", 1, vec![60.0, 63.0]))))
+        );
+
+        // over the notes of a chord
+        let res = param_func_with_values("@note: arp up 2 >> Cm7");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    "note",
+                    ParamExpr::Arp("up", 2, vec![60.0, 63.0, 67.0, 70.0])
+                )
+            ))
+        );
+        assert!(statement("cyc >> sine @note: arp >> Cm7 Fmaj7").is_err());
+    }
+
+    #[test]
+    fn test_chord_params() {
+        let res = event("saw;chord=cmin7;inv=1");
+        println!("Result: {:?}", res);
+        let (cmin7, _) = parse_chord_symbol("cmin7").unwrap();
+        assert_eq!(res, Ok(("", ("saw", vec![("chord", cmin7), ("inv", 1.0)]))));
+
+        let res = param_func_with_values("@chord: cyc >> Cm7 F7 Bb");
+        println!("Result: {:?}", res);
+        let chords: Vec<f32> = ["Cm7", "F7", "Bb"]
+            .iter()
+            .map(|sym| parse_chord_symbol(sym).unwrap().0)
+            .collect();
+        assert_eq!(
+            res,
+            Ok(("", ("chord", ParamExpr::Gen(GenDef::Items("cyc", chords)))))
+        );
+
+        // other parameters don't take chord symbols, so they're left over
+        assert!(param_value("Cmaj7").is_err());
+        let res = param_func_with_values("@freq: cyc >> 100 Cmaj7");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                " Cmaj7",
+                ("freq", ParamExpr::Gen(GenDef::Items("cyc", vec![100.0])))
+            ))
+        );
+        let (rest, _) = pattern_line("cyc >> saw @freq: cyc >> 100 Cmaj7").unwrap();
+        assert_eq!(rest, " Cmaj7");
+    }
+
     #[test]
     fn test_param_func_header() {
         let res = param_func_header("@rate");
@@ -176,6 +1216,166 @@ mod tests {
         assert!(res.is_ok());
     }
 
+    #[test]
+    fn test_degree_param() {
+        assert_eq!(
+            event("sine;degree=2;oct=3"),
+            Ok(("", ("sine", vec![("degree", 2.0), ("oct", 3.0)])))
+        );
+    }
+
+    #[test]
+    fn test_key_definition() {
+        assert_eq!(key_definition("key d dorian"), Ok(("", (2, "dorian"))));
+        assert_eq!(key_definition("key Bb  minor"), Ok(("", (10, "minor"))));
+        assert!(key_definition("key dorian").is_err());
+    }
+
+    #[test]
+    fn test_tuning_definition() {
+        assert_eq!(
+            tuning_definition("tuning bohlen-pierce"),
+            Ok(("", "bohlen-pierce"))
+        );
+        assert!(tuning_definition("tuning").is_err());
+    }
+
+    #[test]
+    fn test_groove_statements() {
+        assert_eq!(
+            groove_definition("groove lazy = 0:1, 0.1:0.8"),
+            Ok(("", ("lazy", vec![(0.0, 1.0), (0.1, 0.8)])))
+        );
+        assert_eq!(groove_selection("groove mpc16"), Ok(("", "mpc16")));
+        assert_eq!(swing_setting("swing 58"), Ok(("", 58.0)));
+
+        let res = pattern_line("cyc >> bd sn @groove: shuffle @swing: cyc >> 50 66");
+        println!("Result: {:?}", res);
+        let (rest, (_, params)) = res.unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(params[0], ("groove", ParamExpr::Groove("shuffle")));
+        assert_eq!(params[1].0, "swing");
+    }
+
+    #[test]
+    fn test_nudge_params() {
+        assert_eq!(
+            event("sn;nudgestep=-0.25;nudge=5"),
+            Ok(("", ("sn", vec![("nudgestep", -0.25), ("nudge", 5.0)])))
+        );
+
+        let res = param_func_with_values("@nudge: rnd >> -5 0 5");
+        println!("Result: {:?}", res);
+        assert_eq!(
+            res,
+            Ok((
+                "",
+                (
+                    "nudge",
+                    ParamExpr::Gen(GenDef::Items("rnd", vec![-5.0, 0.0, 5.0]))
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_line_rate() {
+        assert_eq!(line_rate("/2 cyc >> bd"), Ok(("cyc >> bd", (1, 2))));
+        assert_eq!(line_rate("*3 cyc >> bd"), Ok(("cyc >> bd", (3, 1))));
+        assert_eq!(line_rate("3:4  cyc >> bd"), Ok(("cyc >> bd", (3, 4))));
+        assert!(line_rate("cyc >> bd").is_err());
+        assert!(line_rate("/0 cyc >> bd").is_err());
+        assert!(line_rate("*16 cyc >> bd").is_ok());
+        assert!(line_rate("*17 cyc >> bd").is_err());
+        assert!(line_rate("3:1000000000 cyc >> bd").is_err());
+        assert!(statement("*1000000000 cyc >> bd").is_err());
+    }
+
+    #[test]
+    fn test_tempo_setting() {
+        assert_eq!(tempo_setting("tempo 128"), Ok(("", (128.0, None))));
+        assert_eq!(
+            tempo_setting("tempo 120bpm -> 140bpm over 8"),
+            Ok(("", (120.0, Some((140.0, 8)))))
+        );
+        // the tempo can't stand still or run backwards
+        assert!(tempo_setting("tempo 0").is_err());
+        assert!(tempo_setting("tempo -120").is_err());
+        assert!(all_consuming(tempo_setting)("tempo 120 -> 0 over 4").is_err());
+        assert!(statement("tempo 120 -> -1bpm over 4").is_err());
+
+        // a control line sequencing the tempo
+        let res = pattern_line("cyc >> ctrl @bpm: ramp >> 120 140 32");
+        println!("Result: {:?}", res);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_quantize_setting() {
+        assert_eq!(quantize_setting("quantize off"), Ok(("", Quantize::Off)));
+        assert_eq!(
+            quantize_setting("quantize bar"),
+            Ok(("", Quantize::Bars(1)))
+        );
+        assert_eq!(quantize_setting("quantize 4"), Ok(("", Quantize::Bars(4))));
+        assert_eq!(
+            quantize_setting("quantize line 2"),
+            Ok(("", Quantize::Line(2)))
+        );
+        assert!(quantize_setting("quantize 0").is_err());
+    }
+
+    #[test]
+    fn test_statements() {
+        assert_eq!(
+            statement("groove lazy = 0:1, 0.1:0.8"),
+            Ok((
+                "",
+                Statement::GrooveDefinition("lazy", vec![(0.0, 1.0), (0.1, 0.8)])
+            ))
+        );
+        assert_eq!(
+            statement("groove lazy"),
+            Ok(("", Statement::GrooveSelection("lazy")))
+        );
+        assert_eq!(
+            statement("let hat=hh;lvl=0.5"),
+            Ok(("", Statement::Variable("hat", ("hh", vec![("lvl", 0.5)]))))
+        );
+        match statement("/2 cyc >> bd sn") {
+            Ok(("", Statement::Pattern(rate, _))) => assert_eq!(rate, (1, 2)),
+            res => panic!("pattern not parsed: {:?}", res),
+        }
+        assert!(statement("xyz >> bd").is_err());
+
+        // incomplete or trailing input
+        assert_eq!(
+            statement("tempo 120 -> 140 over 4"),
+            Ok(("", Statement::Tempo(120.0, Some((140.0, 4)))))
+        );
+        assert!(statement("tempo 120 -> 140").is_err());
+        assert!(statement("tempo 120bpm ->").is_err());
+        assert!(statement("cyc >> saw @freq: cyc >> 100 Cmaj7").is_err());
+        assert!(statement("quantize line").is_err());
+
+        // the demo code
+        for line in [
+            "cyc >> bd ~ ~ ~ sn ~ ~ ~ @del: rnd >> 0.0 0.1 0.2 @rev: rnd >> 0.0 0.1 0.2",
+            "cyc >> saw ~ ~ saw ~ @freq: cyc >> 100 150 200 80 @lpf: bounce >> 200 500 20",
+        ] {
+            assert!(matches!(
+                statement(line),
+                Ok(("", Statement::Pattern(_, _)))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_freeze_command() {
+        assert_eq!(freeze_command("freeze 2 16"), Ok(("", (2, 16))));
+        assert!(freeze_command("freeze 16").is_err());
+    }
+
     #[test]
     fn test_var_def_with_param() {
         let res = variable_definiton("let xs=sine;lvl=0.0");
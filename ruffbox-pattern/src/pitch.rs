@@ -0,0 +1,246 @@
+// PITCH
+// Conversions between note numbers and frequencies, chord symbols and voicings.
+
+/// MIDI-style note number (A4 = 69) to frequency in Hz, equal temperament.
+pub fn note_to_freq(note: f32) -> f32 {
+    440.0 * 2.0_f32.powf((note - 69.0) / 12.0)
+}
+
+// CHORDS
+// Chord qualities with their intervals in semitones. The order matters for parsing,
+// as the first quality that matches the symbol wins (so "maj7" has to come before "m").
+pub const CHORD_QUALITIES: &[(&str, &[i32])] = &[
+    ("maj9", &[0, 4, 7, 11, 14]),
+    ("maj7", &[0, 4, 7, 11]),
+    ("maj", &[0, 4, 7]),
+    ("min7", &[0, 3, 7, 10]),
+    ("min", &[0, 3, 7]),
+    ("m7b5", &[0, 3, 6, 10]),
+    ("m9", &[0, 3, 7, 10, 14]),
+    ("m7", &[0, 3, 7, 10]),
+    ("m6", &[0, 3, 7, 9]),
+    ("m", &[0, 3, 7]),
+    ("dim7", &[0, 3, 6, 9]),
+    ("dim", &[0, 3, 6]),
+    ("aug", &[0, 4, 8]),
+    ("sus2", &[0, 2, 7]),
+    ("sus4", &[0, 5, 7]),
+    ("9", &[0, 4, 7, 10, 14]),
+    ("7", &[0, 4, 7, 10]),
+    ("6", &[0, 4, 7, 9]),
+    ("", &[0, 4, 7]),
+];
+
+/// Pitch class (C = 0) of a note name like "c", "F#" or "Bb", and the number of chars consumed.
+pub fn parse_pitch_class(symbol: &str) -> Option<(i32, usize)> {
+    let mut chars = symbol.chars();
+    let root = match chars.next()?.to_ascii_lowercase() {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    match chars.next() {
+        Some('#') => Some(((root + 1) % 12, 2)),
+        Some('b') => Some(((root + 11) % 12, 2)),
+        _ => Some((root, 1)),
+    }
+}
+
+/// Chords are encoded as numbers, so they can be sequenced by the regular parameter generators.
+/// Returns the chord code and the number of chars consumed.
+pub fn parse_chord_symbol(symbol: &str) -> Option<(f32, usize)> {
+    let (root, root_len) = parse_pitch_class(symbol)?;
+    let rest = &symbol[root_len..];
+    for (idx, (quality, _)) in CHORD_QUALITIES.iter().enumerate() {
+        if rest.starts_with(quality) {
+            return Some(((idx as i32 * 12 + root) as f32, root_len + quality.len()));
+        }
+    }
+    None
+}
+
+const PITCH_CLASS_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// The symbol of an encoded chord, so it can be written back into the code.
+pub fn chord_name(code: f32) -> Option<String> {
+    if code < 0.0 {
+        return None;
+    }
+    let code = code as usize;
+    CHORD_QUALITIES
+        .get(code / 12)
+        .map(|(quality, _)| format!("{}{}", PITCH_CLASS_NAMES[code % 12], quality))
+}
+
+/// Root pitch class and intervals of an encoded chord.
+pub fn decode_chord(code: f32) -> Option<(i32, &'static [i32])> {
+    if code < 0.0 {
+        return None;
+    }
+    let code = code as usize;
+    CHORD_QUALITIES
+        .get(code / 12)
+        .map(|(_, intervals)| ((code % 12) as i32, *intervals))
+}
+
+/// Octaves notes can be placed in, C-1 (note 0) to C9.
+pub const MIN_OCTAVE: i32 = -1;
+pub const MAX_OCTAVE: i32 = 9;
+
+/// Inversions and the spread move voices up at most this many octaves.
+pub const CHORD_MAX_OCTAVES: usize = 4;
+
+/// Note numbers of a chord, rooted in the given octave (C4 = 60).
+/// The inversion moves the lowest notes up an octave, the spread
+/// moves every second voice up by that many octaves.
+pub fn chord_voicing(code: f32, octave: i32, inversion: usize, spread: usize) -> Vec<f32> {
+    let (root, intervals) = match decode_chord(code) {
+        Some(chord) => chord,
+        None => return Vec::new(),
+    };
+
+    let octave = octave.clamp(MIN_OCTAVE, MAX_OCTAVE);
+    let inversion = inversion.min(intervals.len() * CHORD_MAX_OCTAVES);
+    let spread = spread.min(CHORD_MAX_OCTAVES);
+
+    let base = 12 * (octave + 1) + root;
+    let mut notes: Vec<i32> = intervals.iter().map(|i| base + i).collect();
+
+    for _ in 0..inversion {
+        notes.sort_unstable();
+        notes[0] += 12;
+    }
+    notes.sort_unstable();
+
+    for note in notes.iter_mut().skip(1).step_by(2) {
+        *note += 12 * spread as i32;
+    }
+
+    notes.into_iter().map(|n| n as f32).collect()
+}
+
+// SCALES
+pub const SCALES: &[(&str, &[i32])] = &[
+    ("major", &[0, 2, 4, 5, 7, 9, 11]),
+    ("ionian", &[0, 2, 4, 5, 7, 9, 11]),
+    ("dorian", &[0, 2, 3, 5, 7, 9, 10]),
+    ("phrygian", &[0, 1, 3, 5, 7, 8, 10]),
+    ("lydian", &[0, 2, 4, 6, 7, 9, 11]),
+    ("mixolydian", &[0, 2, 4, 5, 7, 9, 10]),
+    ("minor", &[0, 2, 3, 5, 7, 8, 10]),
+    ("aeolian", &[0, 2, 3, 5, 7, 8, 10]),
+    ("locrian", &[0, 1, 3, 5, 6, 8, 10]),
+    ("harmonic", &[0, 2, 3, 5, 7, 8, 11]),
+    ("melodic", &[0, 2, 3, 5, 7, 9, 11]),
+    ("pentatonic", &[0, 2, 4, 7, 9]),
+    ("minpent", &[0, 3, 5, 7, 10]),
+    ("blues", &[0, 3, 5, 6, 7, 10]),
+    ("wholetone", &[0, 2, 4, 6, 8, 10]),
+    ("chromatic", &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]),
+];
+
+/// A session key, i.e. "key d dorian", to resolve scale degrees in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Key {
+    pub root: i32,
+    pub intervals: &'static [i32],
+}
+
+impl Key {
+    pub fn from_parsed(root: i32, scale_name: &str) -> Option<Self> {
+        SCALES
+            .iter()
+            .find(|(name, _)| *name == scale_name)
+            .map(|(_, intervals)| Key {
+                root: root.rem_euclid(12),
+                intervals,
+            })
+    }
+
+    /// Note number of a (rounded) scale degree, degree 0 being the root in the given octave.
+    /// Degrees beyond the scale continue in the next octaves, negative degrees go down.
+    pub fn degree_to_note(&self, degree: f32, octave: i32) -> f32 {
+        let degree = degree.round() as i32;
+        let len = self.intervals.len() as i32;
+        let octave = (octave.clamp(MIN_OCTAVE, MAX_OCTAVE) + degree.div_euclid(len))
+            .clamp(MIN_OCTAVE, MAX_OCTAVE);
+        let interval = self.intervals[degree.rem_euclid(len) as usize];
+        (12 * (octave + 1) + self.root + interval) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note_to_freq() {
+        assert_eq!(note_to_freq(69.0), 440.0);
+        assert_eq!(note_to_freq(81.0), 880.0);
+        assert!((note_to_freq(60.0) - 261.6256).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_chord_symbols() {
+        let (cm7, len) = parse_chord_symbol("Cm7").unwrap();
+        assert_eq!(len, 3);
+        assert_eq!(chord_voicing(cm7, 4, 0, 0), [60.0, 63.0, 67.0, 70.0]);
+
+        let (cmin7, _) = parse_chord_symbol("cmin7").unwrap();
+        assert_eq!(decode_chord(cmin7), decode_chord(cm7));
+
+        let (bb, len) = parse_chord_symbol("Bb").unwrap();
+        assert_eq!(len, 2);
+        assert_eq!(chord_voicing(bb, 3, 0, 0), [58.0, 62.0, 65.0]);
+
+        let (fs_maj7, _) = parse_chord_symbol("F#maj7").unwrap();
+        assert_eq!(chord_voicing(fs_maj7, 4, 0, 0), [66.0, 70.0, 73.0, 77.0]);
+
+        assert!(parse_chord_symbol("x7").is_none());
+
+        // names parse back to the same chord
+        for symbol in ["Cm7", "A#maj9", "Bb", "Esus4"] {
+            let (code, _) = parse_chord_symbol(symbol).unwrap();
+            let name = chord_name(code).unwrap();
+            assert_eq!(parse_chord_symbol(&name), Some((code, name.len())));
+        }
+        assert_eq!(chord_name(cm7).as_deref(), Some("Cm7"));
+    }
+
+    #[test]
+    fn test_scale_degrees() {
+        let d_dorian = Key::from_parsed(2, "dorian").unwrap();
+        let notes: Vec<f32> = (0..8)
+            .map(|d| d_dorian.degree_to_note(d as f32, 4))
+            .collect();
+        assert_eq!(notes, [62.0, 64.0, 65.0, 67.0, 69.0, 71.0, 72.0, 74.0]);
+        assert_eq!(d_dorian.degree_to_note(-1.0, 4), 60.0);
+        assert_eq!(d_dorian.degree_to_note(1.2, 3), 52.0);
+        assert!(Key::from_parsed(2, "nonexistent").is_none());
+    }
+
+    #[test]
+    fn test_chord_voicing_options() {
+        let (c, _) = parse_chord_symbol("C").unwrap();
+        assert_eq!(chord_voicing(c, 4, 1, 0), [64.0, 67.0, 72.0]);
+        assert_eq!(chord_voicing(c, 4, 2, 0), [67.0, 72.0, 76.0]);
+        assert_eq!(chord_voicing(c, 4, 0, 1), [60.0, 76.0, 67.0]);
+
+        // out of range values are limited
+        assert_eq!(chord_voicing(c, 4, 1_000_000_000, 0), [108.0, 112.0, 115.0]);
+        assert_eq!(chord_voicing(c, 4, 0, usize::MAX), [60.0, 112.0, 67.0]);
+        assert_eq!(chord_voicing(c, i32::MAX, 0, 0), [120.0, 124.0, 127.0]);
+        assert_eq!(chord_voicing(c, i32::MIN, 0, 0), [0.0, 4.0, 7.0]);
+
+        let key = Key::from_parsed(0, "major").unwrap();
+        assert_eq!(key.degree_to_note(1e9, 4), 131.0);
+        assert_eq!(key.degree_to_note(0.0, i32::MIN), 0.0);
+    }
+}
@@ -0,0 +1,261 @@
+// SCALA
+// Parsers for Scala tuning files (.scl) and keyboard mappings (.kbm),
+// see https://www.huygens-fokker.org/scala/scl_format.html
+
+/// A scale, as pitches in cents above the (implicit) 1/1.
+/// The last pitch is the period, usually the octave.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScalaScale {
+    pub description: String,
+    pub pitches: Vec<f64>,
+}
+
+/// A keyboard mapping, assigning note numbers to scale degrees.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyboardMapping {
+    pub first_note: i32,
+    pub last_note: i32,
+    pub middle_note: i32,
+    pub reference_note: i32,
+    pub reference_freq: f64,
+    pub octave_degree: usize,
+    /// empty for a linear mapping, None for unmapped keys
+    pub mapping: Vec<Option<usize>>,
+}
+
+impl KeyboardMapping {
+    /// Linear mapping, scale starting at middle C and A4 tuned to 440 Hz.
+    pub fn linear(scale: &ScalaScale) -> Self {
+        KeyboardMapping {
+            first_note: 0,
+            last_note: 127,
+            middle_note: 60,
+            reference_note: 69,
+            reference_freq: 440.0,
+            octave_degree: scale.pitches.len(),
+            mapping: Vec::new(),
+        }
+    }
+}
+
+// lines that aren't comments, the description line might be empty
+fn content_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.starts_with('!'))
+}
+
+// only the first token of a line counts, the rest may be a comment
+fn first_token(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+/// Pitches with a period are cents, all others ratios like "3/2" or "2".
+fn parse_pitch(line: &str) -> Result<f64, String> {
+    let token = first_token(line);
+    if token.contains('.') {
+        return token
+            .parse::<f64>()
+            .map_err(|_| format!("invalid cents value \"{}\"", token));
+    }
+
+    let mut parts = token.splitn(2, '/');
+    let num = parts.next().unwrap_or("").parse::<f64>();
+    let den = parts.next().unwrap_or("1").parse::<f64>();
+    match (num, den) {
+        (Ok(num), Ok(den)) if num > 0.0 && den > 0.0 => Ok(1200.0 * (num / den).log2()),
+        _ => Err(format!("invalid ratio \"{}\"", token)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(line: Option<&str>, what: &str) -> Result<T, String> {
+    let line = line.ok_or_else(|| format!("missing {}", what))?;
+    first_token(line)
+        .parse::<T>()
+        .map_err(|_| format!("invalid {} \"{}\"", what, line))
+}
+
+pub fn parse_scl(text: &str) -> Result<ScalaScale, String> {
+    let mut lines = content_lines(text);
+    let description = lines
+        .next()
+        .ok_or_else(|| "missing description".to_string())?
+        .to_string();
+    let count: usize = parse_number(lines.next(), "number of notes")?;
+
+    let pitches = lines
+        .filter(|line| !line.is_empty())
+        .take(count)
+        .map(parse_pitch)
+        .collect::<Result<Vec<f64>, String>>()?;
+
+    if pitches.len() != count || count == 0 {
+        return Err(format!("expected {} pitches, got {}", count, pitches.len()));
+    }
+
+    Ok(ScalaScale {
+        description,
+        pitches,
+    })
+}
+
+pub fn parse_kbm(text: &str) -> Result<KeyboardMapping, String> {
+    let mut lines = content_lines(text).filter(|line| !line.is_empty());
+
+    let size: usize = parse_number(lines.next(), "map size")?;
+    let first_note = parse_number(lines.next(), "first note")?;
+    let last_note = parse_number(lines.next(), "last note")?;
+    let middle_note = parse_number(lines.next(), "middle note")?;
+    let reference_note = parse_number(lines.next(), "reference note")?;
+    let reference_freq = parse_number(lines.next(), "reference frequency")?;
+    let octave_degree = parse_number(lines.next(), "octave degree")?;
+
+    let mut mapping = Vec::new();
+    for _ in 0..size {
+        // missing entries at the end count as unmapped
+        match lines.next().map(first_token) {
+            None | Some("x") => mapping.push(None),
+            Some(degree) => mapping.push(Some(
+                degree
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid mapping entry \"{}\"", degree))?,
+            )),
+        }
+    }
+
+    Ok(KeyboardMapping {
+        first_note,
+        last_note,
+        middle_note,
+        reference_note,
+        reference_freq,
+        octave_degree,
+        mapping,
+    })
+}
+
+/// A scale together with the mapping to note numbers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tuning {
+    pub scale: ScalaScale,
+    pub mapping: KeyboardMapping,
+}
+
+impl Tuning {
+    pub fn from_scale(scale: ScalaScale) -> Self {
+        let mapping = KeyboardMapping::linear(&scale);
+        Tuning { scale, mapping }
+    }
+
+    // cents of a scale degree above the 1/1, wrapping around the period
+    fn degree_cents(&self, degree: i32) -> f64 {
+        let len = self.scale.pitches.len() as i32;
+        let period = self.scale.pitches[len as usize - 1];
+        let idx = degree.rem_euclid(len);
+        let pitch = if idx == 0 {
+            0.0
+        } else {
+            self.scale.pitches[idx as usize - 1]
+        };
+        degree.div_euclid(len) as f64 * period + pitch
+    }
+
+    // cents of a note number relative to the middle note, None if unmapped
+    fn note_cents(&self, note: i32) -> Option<f64> {
+        let map = &self.mapping;
+        if note < map.first_note || note > map.last_note {
+            return None;
+        }
+
+        let offset = note - map.middle_note;
+        if map.mapping.is_empty() {
+            return Some(self.degree_cents(offset));
+        }
+
+        let size = map.mapping.len() as i32;
+        let degree = map.mapping[offset.rem_euclid(size) as usize]? as i32;
+        let octave_cents = if map.octave_degree == 0 {
+            self.degree_cents(self.scale.pitches.len() as i32)
+        } else {
+            self.degree_cents(map.octave_degree as i32)
+        };
+        Some(offset.div_euclid(size) as f64 * octave_cents + self.degree_cents(degree))
+    }
+
+    /// Frequency of a note number, fractional notes are interpolated.
+    /// Unmapped notes don't have a frequency.
+    pub fn note_to_freq(&self, note: f32) -> Option<f32> {
+        let reference_cents = self.note_cents(self.mapping.reference_note)?;
+
+        let lower = note.floor() as i32;
+        let frac = (note - note.floor()) as f64;
+        let cents = if frac > 0.0 {
+            let low = self.note_cents(lower)?;
+            let high = self.note_cents(lower + 1)?;
+            low + frac * (high - low)
+        } else {
+            self.note_cents(lower)?
+        };
+
+        let freq = self.mapping.reference_freq * 2.0_f64.powf((cents - reference_cents) / 1200.0);
+        Some(freq as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 0.01, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_parse_scl() {
+        let scale = parse_scl(include_str!("../fixtures/pythagorean.scl")).unwrap();
+        assert_eq!(scale.description, "12-tone Pythagorean scale");
+        assert_eq!(scale.pitches.len(), 12);
+        assert!((scale.pitches[6] - 701.955).abs() < 0.001);
+        assert!((scale.pitches[11] - 1200.0).abs() < 0.001);
+
+        let bp = parse_scl(include_str!("../fixtures/bohlen-pierce.scl")).unwrap();
+        assert_eq!(bp.pitches.len(), 13);
+        assert_eq!(bp.pitches[0], 146.3);
+
+        assert!(parse_scl("broken\n 3\n 1.5\n").is_err());
+        assert!(parse_scl("broken\n 1\n a/b\n").is_err());
+    }
+
+    #[test]
+    fn test_linear_tuning() {
+        let scale = parse_scl(include_str!("../fixtures/pythagorean.scl")).unwrap();
+        let tuning = Tuning::from_scale(scale);
+        assert_close(tuning.note_to_freq(69.0).unwrap(), 440.0);
+        assert_close(tuning.note_to_freq(81.0).unwrap(), 880.0);
+        // the pythagorean fifth above middle c, relative to a4
+        let c4 = tuning.note_to_freq(60.0).unwrap();
+        assert_close(c4, 440.0 / (27.0 / 16.0));
+        assert_close(tuning.note_to_freq(67.0).unwrap(), c4 * 1.5);
+        // fractional notes are in between
+        let between = tuning.note_to_freq(60.5).unwrap();
+        assert!(between > c4 && between < tuning.note_to_freq(61.0).unwrap());
+    }
+
+    #[test]
+    fn test_mapped_tuning() {
+        let scale = parse_scl(include_str!("../fixtures/diatonic.scl")).unwrap();
+        let mapping = parse_kbm(include_str!("../fixtures/white-keys.kbm")).unwrap();
+        assert_eq!(mapping.reference_freq, 432.0);
+        assert_eq!(mapping.mapping.len(), 12);
+        assert_eq!(mapping.mapping[1], None);
+
+        let tuning = Tuning { scale, mapping };
+        assert_close(tuning.note_to_freq(69.0).unwrap(), 432.0);
+        let c4 = tuning.note_to_freq(60.0).unwrap();
+        assert_close(c4, 432.0 * 3.0 / 5.0);
+        assert_close(tuning.note_to_freq(64.0).unwrap(), c4 * 1.25);
+        assert_close(tuning.note_to_freq(72.0).unwrap(), c4 * 2.0);
+        // black keys are unmapped
+        assert!(tuning.note_to_freq(61.0).is_none());
+    }
+}
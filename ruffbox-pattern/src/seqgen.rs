@@ -1,44 +1,199 @@
 use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
 use std::hash::Hash;
-use vom_rs::pfa::Pfa;
+use vom_rs::pfa::{Pfa, Rule};
 
 use decorum::N32;
 
-pub trait SequenceGenerator<T, S> {
+use crate::pitch::note_to_freq;
+
+pub trait SequenceGenerator<T, S>: SequenceGeneratorClone<T, S> {
     fn get_next(&mut self) -> Option<T>;
     fn get_state(&self) -> S;
+
+    /// Whether the last item completed a cycle. Generators without a cycle of
+    /// their own count one cycle per number of items (or states).
+    fn wrapped(&self) -> bool;
+
+    /// Whether the generator is in the same state whenever it wraps,
+    /// so whole cycles can be skipped rather than generated.
+    fn repeats(&self) -> bool {
+        true
+    }
+
+    /// The next n items, without advancing the generator.
+    /// Random generators only show one of the possible futures.
+    fn peek(&self, n: usize) -> Vec<T> {
+        let mut preview = self.clone_box();
+        (0..n).filter_map(|_| preview.get_next()).collect()
+    }
+
+    /// Get the next item for an event scheduled at the given audio time (in seconds).
+    /// Most generators advance per event and simply ignore the time.
+    fn get_next_at(&mut self, _time: f64) -> Option<T> {
+        self.get_next()
+    }
+
+    /// Number of states, for generators built around an automaton.
+    fn automaton_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Boxed generators can be cloned along with their current state.
+pub trait SequenceGeneratorClone<T, S> {
+    fn clone_box(&self) -> Box<dyn SequenceGenerator<T, S>>;
+}
+
+impl<G, T, S> SequenceGeneratorClone<T, S> for G
+where
+    G: 'static + SequenceGenerator<T, S> + Clone,
+{
+    fn clone_box(&self) -> Box<dyn SequenceGenerator<T, S>> {
+        Box::new(self.clone())
+    }
+}
+
+impl<T, S> Clone for Box<dyn SequenceGenerator<T, S>> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
 }
 
 ////////////
 // RANDOM //
 ////////////
 
+#[derive(Clone)]
 pub struct RandomSequenceGenerator<T> {
     items: Vec<T>,
+    count: usize,
 }
 
 impl<T: Copy + Ord> RandomSequenceGenerator<T> {
     pub fn from_seq(seq: &[T]) -> Self {
         RandomSequenceGenerator {
             items: seq.to_vec(),
+            count: 0,
         }
     }
 }
 
-impl<T: Copy> SequenceGenerator<T, usize> for RandomSequenceGenerator<T> {
+impl<T: 'static + Copy> SequenceGenerator<T, usize> for RandomSequenceGenerator<T> {
     fn get_next(&mut self) -> Option<T> {
+        self.count = (self.count + 1) % self.items.len().max(1);
         self.items.choose(&mut rand::thread_rng()).copied()
     }
 
     fn get_state(&self) -> usize {
         0
     }
+
+    fn wrapped(&self) -> bool {
+        self.count == 0
+    }
+}
+
+/////////////
+// SHUFFLE //
+/////////////
+
+// deals out a random permutation of the items before reshuffling
+#[derive(Clone)]
+pub struct ShuffleSequenceGenerator<T> {
+    items: Vec<T>,
+    bag: Vec<T>,
+}
+
+impl<T: Copy> ShuffleSequenceGenerator<T> {
+    pub fn from_seq(seq: &[T]) -> Self {
+        ShuffleSequenceGenerator {
+            items: seq.to_vec(),
+            bag: Vec::new(),
+        }
+    }
+}
+
+impl<T: 'static + Copy> SequenceGenerator<T, usize> for ShuffleSequenceGenerator<T> {
+    fn get_next(&mut self) -> Option<T> {
+        if self.bag.is_empty() {
+            self.bag = self.items.clone();
+            self.bag.shuffle(&mut rand::thread_rng());
+        }
+        self.bag.pop()
+    }
+
+    fn get_state(&self) -> usize {
+        self.items.len() - self.bag.len()
+    }
+
+    fn wrapped(&self) -> bool {
+        self.bag.is_empty()
+    }
+}
+
+//////////////
+// NOREPEAT //
+//////////////
+
+// random choice that never picks the same item twice in succession
+#[derive(Clone)]
+pub struct NoRepeatSequenceGenerator<T> {
+    items: Vec<T>,
+    last: Option<T>,
+    count: usize,
+}
+
+impl<T: Copy + PartialEq> NoRepeatSequenceGenerator<T> {
+    pub fn from_seq(seq: &[T]) -> Self {
+        NoRepeatSequenceGenerator {
+            items: seq.to_vec(),
+            last: None,
+            count: 0,
+        }
+    }
+}
+
+impl<T: 'static + Copy + PartialEq> SequenceGenerator<T, usize> for NoRepeatSequenceGenerator<T> {
+    fn get_next(&mut self) -> Option<T> {
+        let candidates: Vec<T> = self
+            .items
+            .iter()
+            .copied()
+            .filter(|item| Some(*item) != self.last)
+            .collect();
+
+        // if all items are the same, there's no way around repeating
+        let item = if candidates.is_empty() {
+            self.items.choose(&mut rand::thread_rng()).copied()
+        } else {
+            candidates.choose(&mut rand::thread_rng()).copied()
+        };
+
+        self.last = item;
+        self.count = (self.count + 1) % self.items.len().max(1);
+        item
+    }
+
+    fn get_state(&self) -> usize {
+        0
+    }
+
+    fn wrapped(&self) -> bool {
+        self.count == 0
+    }
+
+    fn repeats(&self) -> bool {
+        false
+    }
 }
 
 ////////////
 // CYCLE  //
 ////////////
 
+#[derive(Clone)]
 pub struct CycleSequenceGenerator<T> {
     items: Vec<T>,
     index: usize,
@@ -55,7 +210,7 @@ impl<T: Copy> CycleSequenceGenerator<T> {
     pub fn from_seq_with_index(seq: &Vec<T>, idx: usize) -> Self {
         let mut idx_clamp = idx;
         if seq.len() <= idx {
-            idx_clamp = seq.len() - 1;
+            idx_clamp = seq.len().saturating_sub(1);
         }
         CycleSequenceGenerator {
             items: seq.to_vec(),
@@ -64,9 +219,10 @@ impl<T: Copy> CycleSequenceGenerator<T> {
     }
 }
 
-impl<T: Copy> SequenceGenerator<T, usize> for CycleSequenceGenerator<T> {
+impl<T: 'static + Copy> SequenceGenerator<T, usize> for CycleSequenceGenerator<T> {
     fn get_next(&mut self) -> Option<T> {
-        let item = self.items[self.index];
+        // an empty cycle is possible as a fallback for invalid definitions
+        let item = self.items.get(self.index).copied();
 
         self.index += 1;
 
@@ -74,46 +230,408 @@ impl<T: Copy> SequenceGenerator<T, usize> for CycleSequenceGenerator<T> {
             self.index = 0;
         }
 
-        Some(item)
+        item
     }
 
     fn get_state(&self) -> usize {
         self.index
     }
+
+    fn wrapped(&self) -> bool {
+        self.index == 0
+    }
 }
 
 /////////
 // PFA //
 /////////
 
+/// Parameters passed to the PFA learning algorithm.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PfaLearnParams {
+    /// maximum order (context length) of the learned automaton
+    pub order: usize,
+    /// probability bound, the lower the closer the imitation of the example
+    pub bound: f32,
+    /// history length considered while learning
+    pub history: usize,
+}
+
+impl PfaLearnParams {
+    /// The order has to be at least 1, with a history at least that long,
+    /// and the bound a probability.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.order == 0 {
+            return Err("order has to be at least 1".to_string());
+        }
+        if self.history < self.order {
+            return Err(format!(
+                "history {} shorter than order {}",
+                self.history, self.order
+            ));
+        }
+        if !(self.bound > 0.0 && self.bound < 1.0) {
+            return Err(format!("bound {} not within (0, 1)", self.bound));
+        }
+        Ok(())
+    }
+}
+
+impl Default for PfaLearnParams {
+    fn default() -> Self {
+        PfaLearnParams {
+            order: 3,
+            bound: 0.01,
+            history: 30,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct PfaSequenceGenerator<T: Eq + Copy + Hash + Ord + std::fmt::Debug> {
     pfa: Pfa<T>,
+    // items per cycle, the length of the learned sequence or the number of rules
+    period: usize,
+    count: usize,
 }
 
 impl<T: Eq + Copy + Hash + Ord + std::fmt::Debug> PfaSequenceGenerator<T> {
     pub fn from_seq(seq: &[T]) -> Self {
+        Self::from_seq_with_params(seq, &PfaLearnParams::default())
+    }
+
+    pub fn from_seq_with_params(seq: &[T], params: &PfaLearnParams) -> Self {
         PfaSequenceGenerator {
-            pfa: Pfa::learn(seq, 3, 0.01, 30),
+            pfa: Pfa::learn(seq, params.order, params.bound, params.history),
+            period: seq.len().max(1),
+            count: 0,
+        }
+    }
+
+    /// Build the automaton from explicit variable-order rules,
+    /// given as (history, next symbol, probability).
+    pub fn from_rules(rules: &[(Vec<T>, T, f32)]) -> Result<Self, String> {
+        if rules.is_empty() {
+            return Err("no rules given".to_string());
+        }
+
+        let mut pfa_rules = Vec::new();
+        let mut table: HashMap<&Vec<T>, Vec<T>> = HashMap::new();
+        for (idx, (source, symbol, prob)) in rules.iter().enumerate() {
+            if source.is_empty() {
+                return Err(format!("rule {}: empty history", idx + 1));
+            }
+            if !(*prob > 0.0 && *prob <= 1.0) {
+                return Err(format!(
+                    "rule {}: probability {} not within (0, 1]",
+                    idx + 1,
+                    prob
+                ));
+            }
+            let symbols = table.entry(source).or_default();
+            if symbols.contains(symbol) {
+                return Err(format!("rule {}: duplicate rule", idx + 1));
+            }
+            symbols.push(*symbol);
+            pfa_rules.push(Rule {
+                source: source.clone(),
+                symbol: *symbol,
+                probability: *prob,
+            });
         }
+
+        for (idx, (source, _, _)) in rules.iter().enumerate() {
+            let sum: f32 = rules
+                .iter()
+                .filter(|(other, _, _)| other == source)
+                .map(|(_, _, prob)| prob)
+                .sum();
+            if (sum - 1.0).abs() > 0.01 {
+                return Err(format!(
+                    "rule {}: probabilities after this history sum up to {} instead of 1",
+                    idx + 1,
+                    sum
+                ));
+            }
+        }
+
+        Ok(PfaSequenceGenerator {
+            pfa: Pfa::infer_from_rules(&mut pfa_rules, true),
+            period: rules.len(),
+            count: 0,
+        })
+    }
+
+    /// Number of states of the learned automaton.
+    pub fn size(&self) -> usize {
+        self.pfa.graph.node_count()
     }
 }
 
-impl<T: Eq + Copy + Hash + Ord + std::fmt::Debug> SequenceGenerator<T, usize>
+impl<T: 'static + Eq + Copy + Hash + Ord + std::fmt::Debug> SequenceGenerator<T, usize>
     for PfaSequenceGenerator<T>
 {
     fn get_next(&mut self) -> Option<T> {
+        self.count = (self.count + 1) % self.period;
         self.pfa.next_symbol()
     }
 
     fn get_state(&self) -> usize {
         0
     }
+
+    fn wrapped(&self) -> bool {
+        self.count == 0
+    }
+
+    fn repeats(&self) -> bool {
+        false
+    }
+
+    fn automaton_size(&self) -> Option<usize> {
+        Some(self.size())
+    }
+}
+
+////////////
+// MARKOV //
+////////////
+
+// first-order markov chain with explicitly written transitions
+#[derive(Clone)]
+pub struct MarkovSequenceGenerator<T> {
+    transitions: HashMap<T, Vec<(T, f32)>>,
+    initial: T,
+    current: T,
+    count: usize,
+}
+
+impl<T: Eq + Copy + Hash> MarkovSequenceGenerator<T> {
+    /// Create a markov chain from (source, destination, probability) triples.
+    /// The first source is the initial state. States without outgoing transitions
+    /// lead back to the initial state.
+    pub fn from_transitions(transitions: &[(T, T, f32)]) -> Result<Self, String> {
+        if transitions.is_empty() {
+            return Err("no transitions given".to_string());
+        }
+
+        let mut table: HashMap<T, Vec<(T, f32)>> = HashMap::new();
+        for (idx, (src, dst, prob)) in transitions.iter().enumerate() {
+            if !(*prob > 0.0 && *prob <= 1.0) {
+                return Err(format!(
+                    "transition {}: probability {} not within (0, 1]",
+                    idx + 1,
+                    prob
+                ));
+            }
+            let outgoing = table.entry(*src).or_default();
+            if outgoing.iter().any(|(d, _)| d == dst) {
+                return Err(format!("transition {}: duplicate transition", idx + 1));
+            }
+            outgoing.push((*dst, *prob));
+        }
+
+        for (idx, (src, _, _)) in transitions.iter().enumerate() {
+            let sum: f32 = table[src].iter().map(|(_, p)| p).sum();
+            if (sum - 1.0).abs() > 0.01 {
+                return Err(format!(
+                    "transition {}: outgoing probabilities sum up to {} instead of 1",
+                    idx + 1,
+                    sum
+                ));
+            }
+        }
+
+        Ok(MarkovSequenceGenerator {
+            transitions: table,
+            initial: transitions[0].0,
+            current: transitions[0].0,
+            count: 0,
+        })
+    }
+}
+
+impl<T: 'static + Eq + Copy + Hash> SequenceGenerator<T, usize> for MarkovSequenceGenerator<T> {
+    fn get_next(&mut self) -> Option<T> {
+        let item = self.current;
+
+        self.current = match self.transitions.get(&item) {
+            Some(outgoing) => outgoing
+                .choose_weighted(&mut rand::thread_rng(), |(_, p)| *p)
+                .map(|(d, _)| *d)
+                .unwrap_or(self.initial),
+            None => self.initial,
+        };
+
+        // one cycle per number of states
+        self.count = (self.count + 1) % self.transitions.len();
+        Some(item)
+    }
+
+    fn get_state(&self) -> usize {
+        0
+    }
+
+    fn wrapped(&self) -> bool {
+        self.count == 0
+    }
+
+    fn repeats(&self) -> bool {
+        false
+    }
+}
+
+////////////////////////
+// CELLULAR AUTOMATON //
+////////////////////////
+
+// every generation is computed in the audio worker, so it can't be arbitrarily wide
+pub const CA_MAX_WIDTH: usize = 256;
+
+// elementary (1D) cellular automaton, every generation is played as one bar,
+// live cells yielding the "on" item, dead cells the "off" item
+#[derive(Clone)]
+pub struct CellularAutomatonSequenceGenerator<T> {
+    on: T,
+    off: T,
+    rule: u8,
+    cells: Vec<bool>,
+    index: usize,
+}
+
+impl<T: Copy> CellularAutomatonSequenceGenerator<T> {
+    /// Without a seed, the first generation has a single live cell in the middle.
+    /// Otherwise, the bits of the seed (lowest bit first) are the first generation.
+    /// The width is limited to CA_MAX_WIDTH.
+    pub fn from_params(on: T, off: T, rule: u8, width: usize, seed: Option<u64>) -> Self {
+        let width = width.clamp(1, CA_MAX_WIDTH);
+        let cells = match seed {
+            Some(bits) => (0..width).map(|i| i < 64 && (bits >> i) & 1 == 1).collect(),
+            None => (0..width).map(|i| i == width / 2).collect(),
+        };
+
+        CellularAutomatonSequenceGenerator {
+            on,
+            off,
+            rule,
+            cells,
+            index: 0,
+        }
+    }
+
+    fn evolve(&mut self) {
+        let width = self.cells.len();
+        self.cells = (0..width)
+            .map(|i| {
+                let left = self.cells[(i + width - 1) % width] as u8;
+                let center = self.cells[i] as u8;
+                let right = self.cells[(i + 1) % width] as u8;
+                (self.rule >> (left << 2 | center << 1 | right)) & 1 == 1
+            })
+            .collect();
+    }
+}
+
+impl<T: 'static + Copy> SequenceGenerator<T, usize> for CellularAutomatonSequenceGenerator<T> {
+    fn get_next(&mut self) -> Option<T> {
+        let item = if self.cells[self.index] {
+            self.on
+        } else {
+            self.off
+        };
+
+        self.index += 1;
+
+        if self.index >= self.cells.len() {
+            self.index = 0;
+            self.evolve();
+        }
+
+        Some(item)
+    }
+
+    fn get_state(&self) -> usize {
+        self.index
+    }
+
+    fn wrapped(&self) -> bool {
+        self.index == 0
+    }
+
+    // every generation is a new one
+    fn repeats(&self) -> bool {
+        false
+    }
+}
+
+//////////////
+// L-SYSTEM //
+//////////////
+
+// expansion stops before growing beyond this, to keep things playable
+pub const LSYSTEM_MAX_LEN: usize = 4096;
+// rules that don't grow could otherwise be rewritten forever
+pub const LSYSTEM_MAX_DEPTH: usize = 64;
+
+// the axiom is rewritten "depth" times, the resulting string is cycled
+#[derive(Clone)]
+pub struct LSystemSequenceGenerator<T> {
+    items: Vec<T>,
+    index: usize,
+}
+
+impl<T: Copy + Eq + Hash> LSystemSequenceGenerator<T> {
+    /// Symbols without a rule are kept as they are.
+    pub fn from_params(axiom: &[T], rules: &[(T, Vec<T>)], depth: usize) -> Self {
+        let rule_map: HashMap<T, &Vec<T>> = rules.iter().map(|(sym, rhs)| (*sym, rhs)).collect();
+
+        let mut items = axiom.to_vec();
+        for _ in 0..depth.min(LSYSTEM_MAX_DEPTH) {
+            let mut expanded = Vec::new();
+            for sym in items.iter() {
+                match rule_map.get(sym) {
+                    Some(rhs) => expanded.extend(rhs.iter().copied()),
+                    None => expanded.push(*sym),
+                }
+            }
+            // nothing changes anymore, or it grew too long
+            if expanded == items || expanded.len() > LSYSTEM_MAX_LEN {
+                break;
+            }
+            items = expanded;
+        }
+
+        LSystemSequenceGenerator { items, index: 0 }
+    }
+}
+
+impl<T: 'static + Copy> SequenceGenerator<T, usize> for LSystemSequenceGenerator<T> {
+    fn get_next(&mut self) -> Option<T> {
+        let item = self.items.get(self.index).copied();
+
+        self.index += 1;
+
+        if self.index >= self.items.len() {
+            self.index = 0;
+        }
+
+        item
+    }
+
+    fn get_state(&self) -> usize {
+        self.index
+    }
+
+    fn wrapped(&self) -> bool {
+        self.index == 0
+    }
 }
 
 //////////
 // RAMP //
 //////////
 
+#[derive(Clone)]
 pub struct RampSequenceGenerator {
     min: N32,
     inc: N32,
@@ -146,6 +664,82 @@ impl SequenceGenerator<N32, usize> for RampSequenceGenerator {
         let state_raw: f32 = self.step_count.into();
         state_raw as usize
     }
+
+    fn wrapped(&self) -> bool {
+        self.step_count == N32::from(0.0)
+    }
+}
+
+//////////////
+// ENVELOPE //
+//////////////
+
+// breakpoint envelope, linear interpolation between (value, step) points
+#[derive(Clone)]
+pub struct EnvelopeSequenceGenerator {
+    points: Vec<(f32, usize)>,
+    looping: bool,
+    step_count: usize,
+    wrapped: bool,
+}
+
+impl EnvelopeSequenceGenerator {
+    /// The points are sorted by step. Before the first point, its value is held.
+    pub fn from_points(points: &[(f32, usize)], looping: bool) -> Self {
+        let mut sorted = points.to_vec();
+        sorted.sort_by_key(|point| point.1);
+        EnvelopeSequenceGenerator {
+            points: sorted,
+            looping,
+            step_count: 0,
+            wrapped: false,
+        }
+    }
+
+    fn value_at(&self, step: usize) -> Option<f32> {
+        let first = self.points.first()?;
+        if step <= first.1 {
+            return Some(first.0);
+        }
+        for segment in self.points.windows(2) {
+            let (from_val, from_step) = segment[0];
+            let (to_val, to_step) = segment[1];
+            if step <= to_step {
+                let frac = (step - from_step) as f32 / (to_step - from_step) as f32;
+                return Some(from_val + frac * (to_val - from_val));
+            }
+        }
+        self.points.last().map(|point| point.0)
+    }
+}
+
+impl SequenceGenerator<N32, usize> for EnvelopeSequenceGenerator {
+    fn get_next(&mut self) -> Option<N32> {
+        let cur = self.value_at(self.step_count);
+        let last_step = self.points.last().map(|point| point.1).unwrap_or(0);
+
+        self.step_count += 1;
+        // a held one-shot envelope completes a cycle with every step
+        self.wrapped = self.step_count > last_step;
+        if self.wrapped {
+            if self.looping {
+                self.step_count = 0;
+            } else {
+                // one-shot, hold the last value
+                self.step_count = last_step;
+            }
+        }
+
+        cur.map(|val| val.into())
+    }
+
+    fn get_state(&self) -> usize {
+        self.step_count
+    }
+
+    fn wrapped(&self) -> bool {
+        self.wrapped
+    }
 }
 
 ////////////
@@ -153,6 +747,7 @@ impl SequenceGenerator<N32, usize> for RampSequenceGenerator {
 ////////////
 
 // sinusoidal bounce
+#[derive(Clone)]
 pub struct BounceSequenceGenerator {
     min: N32,
     degree_inc: N32,
@@ -199,6 +794,407 @@ impl SequenceGenerator<N32, usize> for BounceSequenceGenerator {
         let state_raw: f32 = self.step_count.into();
         state_raw as usize
     }
+
+    fn wrapped(&self) -> bool {
+        let step_count_raw: f32 = self.step_count.into();
+        let steps_raw: f32 = self.steps.into();
+        step_count_raw % steps_raw < 1.0
+    }
+
+    fn repeats(&self) -> bool {
+        self.steps.into_inner().fract() == 0.0
+    }
+}
+
+/////////
+// LFO //
+/////////
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    SampleAndHold,
+}
+
+impl LfoShape {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sine" => Some(LfoShape::Sine),
+            "tri" => Some(LfoShape::Triangle),
+            "saw" => Some(LfoShape::Saw),
+            "square" => Some(LfoShape::Square),
+            "sah" => Some(LfoShape::SampleAndHold),
+            _ => None,
+        }
+    }
+}
+
+// time-based lfo, evaluated against the audio timestamp of the event
+// rather than advancing per event, so the rate doesn't depend on the line density
+#[derive(Clone)]
+pub struct LfoSequenceGenerator {
+    shape: LfoShape,
+    freq: f64, // in Hz
+    min: N32,
+    range: N32,
+    time: f64, // in seconds
+    held_period: Option<i64>,
+    held_value: f32,
+    last_period: Option<i64>,
+    wrapped: bool,
+}
+
+impl LfoSequenceGenerator {
+    pub fn from_params(shape: LfoShape, freq: N32, min: N32, max: N32) -> Self {
+        let freq_raw: f32 = freq.into();
+        LfoSequenceGenerator {
+            shape,
+            freq: freq_raw as f64,
+            min,
+            range: max - min,
+            time: 0.0,
+            held_period: None,
+            held_value: 0.0,
+            last_period: None,
+            wrapped: false,
+        }
+    }
+
+    // unipolar value (0 to 1) at the current time
+    fn unipolar(&mut self) -> f32 {
+        let cycles = self.time * self.freq;
+        let phase = (cycles - cycles.floor()) as f32;
+        match self.shape {
+            LfoShape::Sine => 0.5 + 0.5 * (phase * 2.0 * std::f32::consts::PI).sin(),
+            LfoShape::Triangle => {
+                if phase < 0.5 {
+                    2.0 * phase
+                } else {
+                    2.0 - 2.0 * phase
+                }
+            }
+            LfoShape::Saw => phase,
+            LfoShape::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            LfoShape::SampleAndHold => {
+                // draw a new value once per period
+                let period = cycles.floor() as i64;
+                if self.held_period != Some(period) {
+                    self.held_period = Some(period);
+                    self.held_value = rand::thread_rng().gen_range(0.0..1.0);
+                }
+                self.held_value
+            }
+        }
+    }
+}
+
+impl SequenceGenerator<N32, usize> for LfoSequenceGenerator {
+    fn get_next(&mut self) -> Option<N32> {
+        let min_raw: f32 = self.min.into();
+        let range_raw: f32 = self.range.into();
+        let cur: f32 = min_raw + self.unipolar() * range_raw;
+
+        // there's no item count, a cycle is complete once a new period started
+        let period = (self.time * self.freq).floor() as i64;
+        self.wrapped = self.last_period.is_some_and(|last| last != period);
+        self.last_period = Some(period);
+
+        Some(cur.into())
+    }
+
+    fn get_state(&self) -> usize {
+        0
+    }
+
+    fn wrapped(&self) -> bool {
+        self.wrapped
+    }
+
+    fn get_next_at(&mut self, time: f64) -> Option<N32> {
+        self.time = time;
+        self.get_next()
+    }
+}
+
+/////////
+// ARP //
+/////////
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArpMode {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    /// from the outside in, lowest, highest, second lowest, ...
+    Converge,
+}
+
+impl ArpMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "up" => Some(ArpMode::Up),
+            "down" => Some(ArpMode::Down),
+            "updown" => Some(ArpMode::UpDown),
+            "random" => Some(ArpMode::Random),
+            "converge" => Some(ArpMode::Converge),
+            _ => None,
+        }
+    }
+}
+
+// arpeggiates note numbers over an octave range,
+// delivering either note numbers or frequencies
+#[derive(Clone)]
+pub struct ArpSequenceGenerator {
+    notes: Vec<f32>,
+    mode: ArpMode,
+    as_freq: bool,
+    index: usize,
+}
+
+impl ArpSequenceGenerator {
+    pub fn from_notes(notes: &[f32], mode: ArpMode, octaves: usize, as_freq: bool) -> Self {
+        let mut range = Vec::new();
+        for octave in 0..octaves.max(1) {
+            for note in notes.iter() {
+                range.push(note + 12.0 * octave as f32);
+            }
+        }
+        range.sort_by(f32::total_cmp);
+
+        let notes = match mode {
+            ArpMode::Up | ArpMode::Random => range,
+            ArpMode::Down => range.into_iter().rev().collect(),
+            ArpMode::UpDown => {
+                // don't repeat the top and bottom notes
+                let mut updown = range.clone();
+                if range.len() > 2 {
+                    updown.extend(range[1..range.len() - 1].iter().rev());
+                }
+                updown
+            }
+            ArpMode::Converge => {
+                let mut converge = Vec::new();
+                let (mut low, mut high) = (0, range.len());
+                while low < high {
+                    converge.push(range[low]);
+                    low += 1;
+                    if low < high {
+                        high -= 1;
+                        converge.push(range[high]);
+                    }
+                }
+                converge
+            }
+        };
+
+        ArpSequenceGenerator {
+            notes,
+            mode,
+            as_freq,
+            index: 0,
+        }
+    }
+}
+
+impl SequenceGenerator<N32, usize> for ArpSequenceGenerator {
+    fn get_next(&mut self) -> Option<N32> {
+        let note = if self.mode == ArpMode::Random {
+            self.notes.choose(&mut rand::thread_rng()).copied()
+        } else {
+            self.notes.get(self.index).copied()
+        };
+
+        // random mode keeps counting, to know where a cycle ends
+        self.index += 1;
+        if self.index >= self.notes.len() {
+            self.index = 0;
+        }
+
+        note.map(|n| {
+            if self.as_freq {
+                note_to_freq(n).into()
+            } else {
+                n.into()
+            }
+        })
+    }
+
+    fn get_state(&self) -> usize {
+        self.index
+    }
+
+    fn wrapped(&self) -> bool {
+        self.index == 0
+    }
+}
+
+/////////////////
+// COMBINATORS //
+/////////////////
+
+// combined or modified values might overflow or turn into NaN, which can't be
+// sequenced, so the parameter is left out for that event
+fn finite(val: f32) -> Option<N32> {
+    if val.is_finite() {
+        Some(val.into())
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Add,
+}
+
+// combines the outputs of two generators, both advance on every step
+#[derive(Clone)]
+pub struct BinarySequenceGenerator {
+    op: BinaryOp,
+    left: Box<dyn SequenceGenerator<N32, usize>>,
+    right: Box<dyn SequenceGenerator<N32, usize>>,
+}
+
+impl BinarySequenceGenerator {
+    pub fn from_generators(
+        op: BinaryOp,
+        left: Box<dyn SequenceGenerator<N32, usize>>,
+        right: Box<dyn SequenceGenerator<N32, usize>>,
+    ) -> Self {
+        BinarySequenceGenerator { op, left, right }
+    }
+
+    fn combine(&self, left: Option<N32>, right: Option<N32>) -> Option<N32> {
+        match (left, right) {
+            (Some(l), Some(r)) => {
+                let (l, r) = (l.into_inner(), r.into_inner());
+                finite(match self.op {
+                    BinaryOp::Mul => l * r,
+                    BinaryOp::Add => l + r,
+                })
+            }
+            // if one side doesn't deliver, pass the other one on
+            (l, r) => l.or(r),
+        }
+    }
+}
+
+impl SequenceGenerator<N32, usize> for BinarySequenceGenerator {
+    fn get_next(&mut self) -> Option<N32> {
+        let left = self.left.get_next();
+        let right = self.right.get_next();
+        self.combine(left, right)
+    }
+
+    fn get_state(&self) -> usize {
+        self.left.get_state()
+    }
+
+    fn wrapped(&self) -> bool {
+        self.left.wrapped()
+    }
+
+    fn repeats(&self) -> bool {
+        self.left.repeats() && self.right.repeats()
+    }
+
+    fn get_next_at(&mut self, time: f64) -> Option<N32> {
+        let left = self.left.get_next_at(time);
+        let right = self.right.get_next_at(time);
+        self.combine(left, right)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Modifier {
+    /// round to the nearest multiple of the step
+    Quantize(f32),
+    /// map the unipolar (0 to 1) input to the range (min, max)
+    Scale(f32, f32),
+    /// limit to the range (min, max)
+    Clamp(f32, f32),
+    Offset(f32),
+}
+
+impl Modifier {
+    pub fn from_parsed(name: &str, args: &[f32]) -> Result<Self, String> {
+        match (name, args) {
+            ("quantize", [step]) => Ok(Modifier::Quantize(*step)),
+            ("scale", [min, max]) => Ok(Modifier::Scale(*min, *max)),
+            ("clamp", [min, max]) => Ok(Modifier::Clamp(min.min(*max), max.max(*min))),
+            ("offset", [offset]) => Ok(Modifier::Offset(*offset)),
+            _ => Err(format!("{} doesn't take {} argument(s)", name, args.len())),
+        }
+    }
+
+    pub fn apply(&self, val: f32) -> f32 {
+        match self {
+            Modifier::Quantize(step) => {
+                if *step > 0.0 {
+                    (val / step).round() * step
+                } else {
+                    val
+                }
+            }
+            Modifier::Scale(min, max) => min + val * (max - min),
+            Modifier::Clamp(min, max) => val.max(*min).min(*max),
+            Modifier::Offset(offset) => val + offset,
+        }
+    }
+}
+
+// applies a modifier to the output of another generator
+#[derive(Clone)]
+pub struct ModifiedSequenceGenerator {
+    modifier: Modifier,
+    inner: Box<dyn SequenceGenerator<N32, usize>>,
+}
+
+impl ModifiedSequenceGenerator {
+    pub fn from_generator(
+        modifier: Modifier,
+        inner: Box<dyn SequenceGenerator<N32, usize>>,
+    ) -> Self {
+        ModifiedSequenceGenerator { modifier, inner }
+    }
+}
+
+impl SequenceGenerator<N32, usize> for ModifiedSequenceGenerator {
+    fn get_next(&mut self) -> Option<N32> {
+        self.inner
+            .get_next()
+            .and_then(|val| finite(self.modifier.apply(val.into())))
+    }
+
+    fn get_state(&self) -> usize {
+        self.inner.get_state()
+    }
+
+    fn wrapped(&self) -> bool {
+        self.inner.wrapped()
+    }
+
+    fn repeats(&self) -> bool {
+        self.inner.repeats()
+    }
+
+    fn get_next_at(&mut self, time: f64) -> Option<N32> {
+        self.inner
+            .get_next_at(time)
+            .and_then(|val| finite(self.modifier.apply(val.into())))
+    }
 }
 
 #[cfg(test)]
@@ -206,6 +1202,97 @@ mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
 
+    #[test]
+    fn test_shuffle_gen() {
+        let mut shuffle_gen = ShuffleSequenceGenerator::from_seq(&[1, 2, 3, 4]);
+        for _ in 0..3 {
+            let mut results = Vec::new();
+            for _ in 0..4 {
+                results.push(shuffle_gen.get_next().unwrap());
+            }
+            println!("Result: {:?}", results);
+            // every round is a permutation of the items
+            results.sort();
+            assert_eq!(results, [1, 2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn test_wrapped() {
+        let wraps = |generator: &mut dyn SequenceGenerator<i32, usize>, n| -> Vec<bool> {
+            (0..n)
+                .map(|_| {
+                    generator.get_next();
+                    generator.wrapped()
+                })
+                .collect()
+        };
+
+        // random generators count one cycle per number of items
+        let mut shuffle_gen = ShuffleSequenceGenerator::from_seq(&[1, 2, 3]);
+        assert_eq!(
+            wraps(&mut shuffle_gen, 6),
+            [false, false, true, false, false, true]
+        );
+        let mut rnd_gen = RandomSequenceGenerator::from_seq(&[1, 2, 3]);
+        assert_eq!(
+            wraps(&mut rnd_gen, 6),
+            [false, false, true, false, false, true]
+        );
+        let mut cyc_gen = CycleSequenceGenerator::from_seq(&[1, 2]);
+        assert_eq!(wraps(&mut cyc_gen, 4), [false, true, false, true]);
+
+        let env_wraps = |looping| -> Vec<bool> {
+            let mut env_gen =
+                EnvelopeSequenceGenerator::from_points(&[(0.0, 0), (1.0, 2)], looping);
+            (0..5)
+                .map(|_| {
+                    env_gen.get_next();
+                    env_gen.wrapped()
+                })
+                .collect()
+        };
+        assert_eq!(env_wraps(true), [false, false, true, false, false]);
+        // a one-shot envelope holding its last value wraps with every step
+        assert_eq!(env_wraps(false), [false, false, true, true, true]);
+    }
+
+    #[test]
+    fn test_norepeat_gen() {
+        let mut norepeat_gen = NoRepeatSequenceGenerator::from_seq(&[1, 2, 2, 3]);
+        let mut results = Vec::new();
+        for _ in 0..50 {
+            results.push(norepeat_gen.get_next().unwrap());
+        }
+        println!("Result: {:?}", results);
+        for pair in results.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+        }
+
+        let mut single_gen = NoRepeatSequenceGenerator::from_seq(&[1]);
+        assert_eq!(single_gen.get_next(), Some(1));
+        assert_eq!(single_gen.get_next(), Some(1));
+    }
+
+    #[test]
+    fn test_peek_keeps_state() {
+        let mut cycle_gen = CycleSequenceGenerator::from_seq(&[1, 2, 3]);
+        cycle_gen.get_next();
+        assert_eq!(cycle_gen.peek(4), [2, 3, 1, 2]);
+        assert_eq!(cycle_gen.get_next(), Some(2));
+
+        // boxed generators, including combined ones, can be previewed as well
+        let ramp: Box<dyn SequenceGenerator<N32, usize>> = Box::new(
+            RampSequenceGenerator::from_params((0.0).into(), (3.0).into(), (3.0).into()),
+        );
+        let mut offset = ModifiedSequenceGenerator::from_generator(Modifier::Offset(10.0), ramp);
+        let preview = offset.peek(2);
+        assert_eq!(
+            preview,
+            [offset.get_next().unwrap(), offset.get_next().unwrap()]
+        );
+    }
+
     #[test]
     fn test_bounce_gen() {
         let mut bounce_gen =
@@ -228,6 +1315,311 @@ mod tests {
         println!("Result: {:?}", results);
     }
 
+    #[test]
+    fn test_lfo_gen_is_time_based() {
+        let mut lfo_gen = LfoSequenceGenerator::from_params(
+            LfoShape::Saw,
+            (0.25).into(),
+            (0.0).into(),
+            (1.0).into(),
+        );
+        // same time, same value, no matter how often it's called
+        assert_eq!(lfo_gen.get_next_at(1.0), lfo_gen.get_next_at(1.0));
+        assert_eq!(lfo_gen.get_next_at(1.0), Some((0.25).into()));
+        assert_eq!(lfo_gen.get_next_at(2.0), Some((0.5).into()));
+        // wraps around after one period
+        assert_eq!(lfo_gen.get_next_at(4.0), Some((0.0).into()));
+    }
+
+    #[test]
+    fn test_lfo_gen_shapes() {
+        let mut sine_gen = LfoSequenceGenerator::from_params(
+            LfoShape::Sine,
+            (1.0).into(),
+            (0.0).into(),
+            (2.0).into(),
+        );
+        assert_eq!(sine_gen.get_next_at(0.0), Some((1.0).into()));
+
+        let mut sqr_gen = LfoSequenceGenerator::from_params(
+            LfoShape::Square,
+            (1.0).into(),
+            (0.0).into(),
+            (1.0).into(),
+        );
+        assert_eq!(sqr_gen.get_next_at(0.25), Some((1.0).into()));
+        assert_eq!(sqr_gen.get_next_at(0.75), Some((0.0).into()));
+
+        let mut sah_gen = LfoSequenceGenerator::from_params(
+            LfoShape::SampleAndHold,
+            (1.0).into(),
+            (0.0).into(),
+            (1.0).into(),
+        );
+        // held within one period
+        assert_eq!(sah_gen.get_next_at(0.1), sah_gen.get_next_at(0.9));
+    }
+
+    #[test]
+    fn test_markov_gen() {
+        let mut markov_gen =
+            MarkovSequenceGenerator::from_transitions(&[(1, 2, 1.0), (2, 1, 0.5), (2, 3, 0.5)])
+                .unwrap();
+        let mut results = Vec::new();
+        for _ in 0..20 {
+            results.push(markov_gen.get_next().unwrap());
+        }
+        println!("Result: {:?}", results);
+        assert_eq!(results[0], 1);
+        // 1 is always followed by 2, 3 (no outgoing transitions) returns to the start
+        for pair in results.windows(2) {
+            match pair[0] {
+                1 => assert_eq!(pair[1], 2),
+                2 => assert!(pair[1] == 1 || pair[1] == 3),
+                _ => assert_eq!(pair[1], 1),
+            }
+        }
+    }
+
+    #[test]
+    fn test_markov_gen_validation() {
+        assert!(MarkovSequenceGenerator::<u64>::from_transitions(&[]).is_err());
+        assert!(MarkovSequenceGenerator::from_transitions(&[(1, 2, 1.5)]).is_err());
+        assert!(MarkovSequenceGenerator::from_transitions(&[(1, 2, 0.5)]).is_err());
+        assert!(MarkovSequenceGenerator::from_transitions(&[(1, 2, 0.5), (1, 2, 0.5)]).is_err());
+        assert!(MarkovSequenceGenerator::from_transitions(&[(1, 2, 0.7), (1, 1, 0.3)]).is_ok());
+    }
+
+    #[test]
+    fn test_pfa_gen_from_rules() {
+        let rules = vec![
+            (vec![1, 2], 3, 0.5),
+            (vec![1, 2], 1, 0.5),
+            (vec![2, 3], 1, 1.0),
+            (vec![3, 1], 2, 1.0),
+            (vec![2, 1], 2, 1.0),
+        ];
+        let mut pfa_gen = PfaSequenceGenerator::from_rules(&rules).unwrap();
+        for _ in 0..10 {
+            assert!(pfa_gen.get_next().is_some());
+        }
+
+        assert!(PfaSequenceGenerator::<u64>::from_rules(&[]).is_err());
+        assert!(PfaSequenceGenerator::from_rules(&[(vec![1], 2, 0.0)]).is_err());
+        assert!(PfaSequenceGenerator::from_rules(&[(vec![], 2, 1.0)]).is_err());
+        assert!(PfaSequenceGenerator::from_rules(&[(vec![1], 2, 0.5)]).is_err());
+        assert!(
+            PfaSequenceGenerator::from_rules(&[(vec![1, 2], 1, 0.5), (vec![1, 2], 1, 0.5)])
+                .is_err()
+        );
+        assert!(
+            PfaSequenceGenerator::from_rules(&[(vec![1, 2], 1, 0.7), (vec![2, 2], 1, 0.3)])
+                .is_err()
+        );
+        assert!(
+            PfaSequenceGenerator::from_rules(&[(vec![1, 2], 1, 0.7), (vec![1, 2], 2, 0.3)]).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_ca_gen() {
+        let mut ca_gen = CellularAutomatonSequenceGenerator::from_params(1, 0, 90, 8, None);
+        let mut results = Vec::new();
+        for _ in 0..24 {
+            results.push(ca_gen.get_next().unwrap());
+        }
+        println!("Result: {:?}", results);
+        // rule 90 grows a sierpinski triangle from the middle cell
+        assert_eq!(results[0..8], [0, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(results[8..16], [0, 0, 0, 1, 0, 1, 0, 0]);
+        assert_eq!(results[16..24], [0, 0, 1, 0, 0, 0, 1, 0]);
+
+        let mut seeded_gen = CellularAutomatonSequenceGenerator::from_params(1, 0, 30, 4, Some(5));
+        let mut results = Vec::new();
+        for _ in 0..4 {
+            results.push(seeded_gen.get_next().unwrap());
+        }
+        assert_eq!(results, [1, 0, 1, 0]);
+
+        let wide_gen = CellularAutomatonSequenceGenerator::from_params(1, 0, 30, 1_000_000, None);
+        assert_eq!(wide_gen.cells.len(), CA_MAX_WIDTH);
+    }
+
+    #[test]
+    fn test_lsystem_gen() {
+        // 1 = bd, 2 = sn, 3 = hh
+        let rules = vec![(1, vec![1, 2]), (2, vec![3, 1])];
+        let mut lsys_gen = LSystemSequenceGenerator::from_params(&[1], &rules, 3);
+        let mut results = Vec::new();
+        for _ in 0..10 {
+            results.push(lsys_gen.get_next().unwrap());
+        }
+        println!("Result: {:?}", results);
+        // 1 -> 1 2 -> 1 2 3 1 -> 1 2 3 1 3 1 2, then cycled
+        assert_eq!(results, [1, 2, 3, 1, 3, 1, 2, 1, 2, 3]);
+
+        // doubling every step, must stop growing at some point
+        let mut big_gen = LSystemSequenceGenerator::from_params(&[1], &[(1, vec![1, 1])], 100);
+        assert!(big_gen.items.len() <= LSYSTEM_MAX_LEN);
+        assert_eq!(big_gen.get_next(), Some(1));
+
+        // rules that don't grow, however deep
+        let mut same_gen =
+            LSystemSequenceGenerator::from_params(&[1, 2], &[(1, vec![2])], usize::MAX);
+        assert_eq!(same_gen.items, [2, 2]);
+        let swap_gen =
+            LSystemSequenceGenerator::from_params(&[1], &[(1, vec![2]), (2, vec![1])], usize::MAX);
+        assert_eq!(swap_gen.items.len(), 1);
+        assert_eq!(same_gen.get_next(), Some(2));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let cyc_gen = CycleSequenceGenerator::from_seq(&[(100.0).into(), (200.0).into()]);
+        let ramp_gen = RampSequenceGenerator::from_params((1.0).into(), (2.0).into(), (2.0).into());
+        let mut mul_gen = BinarySequenceGenerator::from_generators(
+            BinaryOp::Mul,
+            Box::new(cyc_gen),
+            Box::new(ramp_gen),
+        );
+        let mut results = Vec::new();
+        for _ in 0..4 {
+            results.push(mul_gen.get_next().unwrap());
+        }
+        let expected: Vec<N32> = vec![
+            (100.0).into(),
+            (300.0).into(),
+            (200.0).into(),
+            (200.0).into(),
+        ];
+        assert_eq!(results, expected);
+
+        let lfo_gen = LfoSequenceGenerator::from_params(
+            LfoShape::Saw,
+            (1.0).into(),
+            (0.0).into(),
+            (1.0).into(),
+        );
+        let scaled_gen = ModifiedSequenceGenerator::from_generator(
+            Modifier::Scale(100.0, 200.0),
+            Box::new(lfo_gen),
+        );
+        let mut quantized_gen = ModifiedSequenceGenerator::from_generator(
+            Modifier::Quantize(50.0),
+            Box::new(scaled_gen),
+        );
+        // time is passed through to the lfo
+        assert_eq!(quantized_gen.get_next_at(0.3), Some((150.0).into()));
+        assert_eq!(quantized_gen.get_next_at(0.9), Some((200.0).into()));
+
+        assert_eq!(
+            Modifier::from_parsed("clamp", &[2.0, 1.0]),
+            Ok(Modifier::Clamp(1.0, 2.0))
+        );
+        assert_eq!(Modifier::Clamp(1.0, 2.0).apply(3.0), 2.0);
+        assert_eq!(Modifier::Offset(1.0).apply(3.0), 4.0);
+        assert!(Modifier::from_parsed("scale", &[1.0]).is_err());
+    }
+
+    #[test]
+    fn test_combinators_non_finite() {
+        // quantizing to a step of 0 would divide by zero, the value is passed through
+        let mut quantized_gen = ModifiedSequenceGenerator::from_generator(
+            Modifier::Quantize(0.0),
+            Box::new(CycleSequenceGenerator::from_seq(&[(3.3).into()])),
+        );
+        assert_eq!(quantized_gen.get_next(), Some((3.3).into()));
+
+        // infinity times zero is NaN, which is skipped rather than sequenced
+        let mut mul_gen = BinarySequenceGenerator::from_generators(
+            BinaryOp::Mul,
+            Box::new(CycleSequenceGenerator::from_seq(&[
+                f32::INFINITY.into(),
+                (2.0).into(),
+            ])),
+            Box::new(CycleSequenceGenerator::from_seq(&[(0.0).into()])),
+        );
+        assert_eq!(mul_gen.get_next(), None);
+        assert_eq!(mul_gen.get_next(), Some((0.0).into()));
+
+        // and so are overflows
+        let mut offset_gen = ModifiedSequenceGenerator::from_generator(
+            Modifier::Offset(f32::MAX),
+            Box::new(CycleSequenceGenerator::from_seq(&[
+                f32::MAX.into(),
+                (1.0).into(),
+            ])),
+        );
+        assert_eq!(offset_gen.get_next(), None);
+        assert_eq!(offset_gen.get_next(), Some(f32::MAX.into()));
+    }
+
+    #[test]
+    fn test_envelope_gen() {
+        let points = vec![(200.0, 0), (4000.0, 8), (300.0, 16)];
+        let mut env_gen = EnvelopeSequenceGenerator::from_points(&points, true);
+        let mut results = Vec::new();
+        for _ in 0..18 {
+            results.push(env_gen.get_next().unwrap().into_inner());
+        }
+        println!("Result: {:?}", results);
+        assert_eq!(results[0], 200.0);
+        assert_eq!(results[4], 2100.0);
+        assert_eq!(results[8], 4000.0);
+        assert_eq!(results[16], 300.0);
+        // loops back to the start
+        assert_eq!(results[17], 200.0);
+
+        let mut once_gen = EnvelopeSequenceGenerator::from_points(&[(1.0, 2), (0.0, 4)], false);
+        let mut results = Vec::new();
+        for _ in 0..7 {
+            results.push(once_gen.get_next().unwrap().into_inner());
+        }
+        // holds the first value before the first point and the last one at the end
+        assert_eq!(results, [1.0, 1.0, 1.0, 0.5, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_arp_gen() {
+        let arp_notes = |mode, octaves| {
+            let mut arp_gen =
+                ArpSequenceGenerator::from_notes(&[64.0, 60.0, 67.0], mode, octaves, false);
+            let mut results = Vec::new();
+            for _ in 0..6 {
+                results.push(arp_gen.get_next().unwrap().into_inner());
+            }
+            results
+        };
+
+        assert_eq!(
+            arp_notes(ArpMode::Up, 1),
+            [60.0, 64.0, 67.0, 60.0, 64.0, 67.0]
+        );
+        assert_eq!(
+            arp_notes(ArpMode::Down, 1),
+            [67.0, 64.0, 60.0, 67.0, 64.0, 60.0]
+        );
+        assert_eq!(
+            arp_notes(ArpMode::UpDown, 1),
+            [60.0, 64.0, 67.0, 64.0, 60.0, 64.0]
+        );
+        assert_eq!(
+            arp_notes(ArpMode::Up, 2),
+            [60.0, 64.0, 67.0, 72.0, 76.0, 79.0]
+        );
+        assert_eq!(
+            arp_notes(ArpMode::Converge, 2),
+            [60.0, 79.0, 64.0, 76.0, 67.0, 72.0]
+        );
+        for note in arp_notes(ArpMode::Random, 1) {
+            assert!([60.0, 64.0, 67.0].contains(&note));
+        }
+
+        let mut freq_gen = ArpSequenceGenerator::from_notes(&[69.0], ArpMode::Up, 2, true);
+        assert_eq!(freq_gen.get_next(), Some((440.0).into()));
+        assert_eq!(freq_gen.get_next(), Some((880.0).into()));
+    }
+
     #[test]
     fn test_pfa_gen() {
         let in_vec: Vec<N32> = vec![
@@ -256,5 +1648,32 @@ mod tests {
             println!("Result: {:?}", pfa_gen.get_next());
         }
         println!("Result: {:?}", results);
+
+        let params = PfaLearnParams {
+            order: 2,
+            bound: 0.05,
+            history: 10,
+        };
+        assert!(params.validate().is_ok());
+        let pfa_gen = PfaSequenceGenerator::from_seq_with_params(&in_vec, &params);
+        println!("Size: {}", pfa_gen.size());
+        assert!(pfa_gen.size() > 0);
+        assert_eq!(pfa_gen.automaton_size(), Some(pfa_gen.size()));
+
+        let invalid = [
+            PfaLearnParams { order: 0, ..params },
+            PfaLearnParams {
+                order: 4,
+                history: 3,
+                ..params
+            },
+            PfaLearnParams {
+                bound: 1.5,
+                ..params
+            },
+        ];
+        for params in invalid.iter() {
+            assert!(params.validate().is_err());
+        }
     }
 }